@@ -1,6 +1,6 @@
-use std::sync::Arc;
+use std::{sync::{Arc, Mutex}, time::Instant};
 
-use crate::{LogStore, NodeId, Raft, StateMachineStore};
+use crate::{blocking_take::TakeWaiters, NodeId, NodeLogStore, Raft, StateMachineStore};
 
 // Representation of an application state. This struct can be shared around to share
 // instances of raft, store and more.
@@ -8,7 +8,31 @@ pub struct App {
     pub id: NodeId,
     pub addr: String,
     pub raft: Raft,
-    pub log_store: LogStore,
+    pub log_store: NodeLogStore,
     pub state_machine_store: Arc<StateMachineStore>,
     pub config: Arc<openraft::Config>,
+
+    /// The last time this node accepted entries or a heartbeat from a leader.
+    pub leader_contact: Mutex<Option<LeaderContact>>,
+
+    /// Blocking takes parked while this node is the leader.
+    pub take_waiters: Arc<TakeWaiters>,
+}
+
+/// What a follower knew about the leader's log when it last heard from it.
+#[derive(Debug, Clone, Copy)]
+pub struct LeaderContact {
+    /// The leader's commit index at the time, `None` if nothing was committed yet.
+    pub committed: Option<u64>,
+    pub at: Instant,
+}
+
+impl App {
+    pub fn record_leader_contact(&self, committed: Option<u64>) {
+        *self.leader_contact.lock().unwrap() = Some(LeaderContact { committed, at: Instant::now() });
+    }
+
+    pub fn leader_contact(&self) -> Option<LeaderContact> {
+        *self.leader_contact.lock().unwrap()
+    }
 }
@@ -1,5 +1,7 @@
+use std::path::PathBuf;
+
 use clap::Parser;
-use ratus::start_example_raft_node;
+use ratus::{ bootstrap::ClusterConfig, start_raft_node, NodeOptions };
 use system::{ Logger, set_process_name };
 
 #[derive(Parser, Clone, Debug)]
@@ -8,8 +10,37 @@ pub struct Opt {
     #[clap(long)]
     pub id: u64,
 
+    /// Address to serve on. May be left out with `--cluster-config`, which lists it.
+    #[clap(long)]
+    pub http_addr: Option<String>,
+
+    /// Directory to persist the Raft log and snapshots in. Without it they are kept in memory and lost on exit.
+    #[clap(long)]
+    pub data_dir: Option<PathBuf>,
+
+    /// Snapshot once this many log entries were applied since the last snapshot.
+    #[clap(long)]
+    pub snapshot_logs_since_last: Option<u64>,
+
+    /// Also snapshot once the requests applied since the last snapshot add up to this many bytes.
+    #[clap(long)]
+    pub snapshot_max_log_bytes: Option<u64>,
+
+    /// How many log entries already covered by a snapshot to keep for lagging followers.
+    #[clap(long)]
+    pub max_in_snapshot_log_to_keep: Option<u64>,
+
+    /// LZ4 compress the snapshots this node builds.
+    #[clap(long)]
+    pub compress_snapshots: bool,
+
+    /// Largest chunk, in bytes, to send a snapshot to a follower in. Use the same size on every node.
+    #[clap(long)]
+    pub snapshot_max_chunk_size: Option<u64>,
+
+    /// TOML manifest of the cluster's nodes. The lowest-id node initializes the cluster once a quorum of them is reachable.
     #[clap(long)]
-    pub http_addr: String,
+    pub cluster_config: Option<PathBuf>,
 }
 
 #[actix_web::main]
@@ -20,5 +51,30 @@ async fn main() -> std::io::Result<()> {
     set_process_name(format!("ratus-{}", options.id).as_str());
 
     Logger::info("Starting RaTuS node", true);
-    start_example_raft_node(options.id, options.http_addr).await
+    let cluster = options.cluster_config.as_ref().map(ClusterConfig::load).transpose()?;
+    let listed_addr = match &cluster {
+        Some(cluster) => match cluster.node(options.id) {
+            Some(node) => Some(node.addr.clone()),
+            None => {
+                let message = format!("node {} is not listed in the cluster config", options.id);
+                return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, message));
+            }
+        },
+        None => None,
+    };
+    let http_addr = match options.http_addr.or(listed_addr) {
+        Some(http_addr) => http_addr,
+        None => return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "--http-addr or --cluster-config is required")),
+    };
+
+    let node_options = NodeOptions {
+        data_dir: options.data_dir,
+        snapshot_logs_since_last: options.snapshot_logs_since_last,
+        snapshot_max_log_bytes: options.snapshot_max_log_bytes,
+        max_in_snapshot_log_to_keep: options.max_in_snapshot_log_to_keep,
+        compress_snapshots: options.compress_snapshots,
+        snapshot_max_chunk_size: options.snapshot_max_chunk_size,
+        cluster,
+    };
+    start_raft_node(options.id, http_addr, node_options).await
 }
@@ -6,7 +6,7 @@ use system::{ Logger, set_process_name };
 
 use ratus::{
     client::{ RaftClusterClient, Node }, 
-    network::api::ReadRequest, 
+    network::api::{ ReadConsistency, ReadRequest }, 
     store::Request
 };
 
@@ -73,12 +73,14 @@ async fn main() -> anyhow::Result<()> {
     cluster.change_target(&node2);
     let x = cluster.read(&ReadRequest {
         query: query.clone(),
+        consistency: ReadConsistency::Stale,
     }).await?;
     Logger::info(format!("Read tuple from node 2: {:?}", x), true);
 
     cluster.change_target(&node3);
     let x = cluster.read(&ReadRequest {
         query: query,
+        consistency: ReadConsistency::Stale,
     }).await?;
     Logger::info(format!("Read tuple from node 3: {:?}", x), true);
 
@@ -112,6 +114,7 @@ async fn main() -> anyhow::Result<()> {
             any_float().
             any_boolean().
             build(),
+        consistency: ReadConsistency::Stale,
     }).await?;
     Logger::info(format!("Read tuple from node 2: {:?}", x), true);
 
@@ -123,6 +126,7 @@ async fn main() -> anyhow::Result<()> {
             any_float().
             any_boolean().
             build(),
+        consistency: ReadConsistency::Stale,
     }).await?;
     Logger::info(format!("Read tuple from node 1: {:?}", x), true);
 
@@ -130,6 +134,7 @@ async fn main() -> anyhow::Result<()> {
     // Will get and remove the first tuple that matches the query (Not all tuples that match the query)
     // So if two tuples match the query, only one will be removed.
     // Or if there are replicas of the tuple, only one will be removed per query.
+    // To remove all of them at once, in a single log entry, use `Request::GetAll` with `cluster.get_all`.
     let tuple_to_remove = Tuple::query().
         string("Number").
         integer(5).
@@ -154,6 +159,7 @@ async fn main() -> anyhow::Result<()> {
             integer(5).
             any_boolean().
             build(),
+        consistency: ReadConsistency::Stale,
     }).await?;
     Logger::info(format!("Read tuple from node 2: {:?}", x), true);
 
@@ -164,6 +170,7 @@ async fn main() -> anyhow::Result<()> {
             integer(5).
             any_boolean().
             build(),
+        consistency: ReadConsistency::Stale,
     }).await?;
     Logger::info(format!("Read tuple from node 3: {:?}", x), true);
 
@@ -226,12 +233,14 @@ async fn main() -> anyhow::Result<()> {
 
     let x = cluster.read(&ReadRequest {
         query: query.clone(),
+        consistency: ReadConsistency::Stale,
     }).await?;
     Logger::info(format!("Read tuple from node 2: {:?}", x), true);
 
     cluster.change_target(&node3);
     let x = cluster.read(&ReadRequest {
         query: query,
+        consistency: ReadConsistency::Stale,
     }).await?;
     Logger::info(format!("Read tuple from node 3: {:?}", x), true);
 
@@ -261,8 +270,23 @@ async fn main() -> anyhow::Result<()> {
             any_boolean().
             any_float().
             build(),
+        consistency: ReadConsistency::Stale,
     }).await?;
     Logger::info(format!("Read tuple from node 2: {:?}", x), true);
 
+    // A linearizable read does not depend on replication having caught up: node 3 forwards it to
+    // the leader, which confirms it is still the leader with a quorum before reading.
+    cluster.change_target(&node3);
+    let x = cluster.read(&ReadRequest {
+        query: Tuple::query().
+            string("Number").
+            integer(7).
+            any_boolean().
+            any_float().
+            build(),
+        consistency: ReadConsistency::Linearizable,
+    }).await?;
+    Logger::info(format!("Linearizable read of tuple: {:?}", x), true);
+
     Ok(())
 }
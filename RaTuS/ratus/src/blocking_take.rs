@@ -0,0 +1,238 @@
+use std::{
+    collections::VecDeque,
+    sync::{atomic::{AtomicU64, Ordering}, Arc, Mutex},
+};
+
+use tokio::sync::{oneshot, Notify};
+use ts_core::{query_tuple::QueryTuple, store::Store, tuple::Tuple};
+
+use system::Logger;
+
+use crate::{network::api::forward_to_leader, store::Request, typ, NodeId, Raft, StateMachineStore};
+
+/// What a parked take ends with: the tuple taken for it, or the leader to retry with after this
+/// node lost leadership.
+pub type TakeResult = Result<Tuple, typ::ForwardToLeader>;
+
+/// Blocking takes parked on the leader, oldest first.
+#[derive(Default)]
+pub struct TakeWaiters {
+    queue: Mutex<VecDeque<Waiter>>,
+    next_id: AtomicU64,
+    /// Signalled when a waiter is parked, so the dispatcher tries it against the current tuples.
+    parked: Notify,
+}
+
+struct Waiter {
+    id: u64,
+    query: QueryTuple,
+    reply: oneshot::Sender<TakeResult>,
+    state: WaiterState,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum WaiterState {
+    /// Waiting for a match.
+    Parked,
+    /// The dispatcher proposed a take for it and hands it the tuple once committed.
+    Claimed,
+    /// Claimed, and its request timed out meanwhile. Still handed the tuple if the take commits
+    /// one, dropped otherwise.
+    Cancelled,
+}
+
+impl TakeWaiters {
+    /// Parks a take for `query` behind every take parked before it.
+    pub fn park(&self, query: QueryTuple) -> (u64, oneshot::Receiver<TakeResult>) {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let (reply, receiver) = oneshot::channel();
+        self.queue.lock().unwrap().push_back(Waiter { id, query, reply, state: WaiterState::Parked });
+        self.parked.notify_one();
+        (id, receiver)
+    }
+
+    /// Drops a parked take, e.g. one that timed out. Returns `false` if it is no longer parked: the
+    /// dispatcher already proposed a take for it, or answered it, and the receiver still gets the
+    /// outcome, so a tuple taken for it is not lost.
+    pub fn cancel(&self, id: u64) -> bool {
+        let mut queue = self.queue.lock().unwrap();
+        let Some(position) = queue.iter().position(|waiter| waiter.id == id) else { return false };
+        match queue[position].state {
+            WaiterState::Parked => {
+                queue.remove(position);
+                true
+            }
+            WaiterState::Claimed | WaiterState::Cancelled => {
+                queue[position].state = WaiterState::Cancelled;
+                false
+            }
+        }
+    }
+
+    fn pending(&self) -> Vec<(u64, QueryTuple)> {
+        let queue = self.queue.lock().unwrap();
+        queue.iter().filter(|waiter| waiter.state == WaiterState::Parked).map(|waiter| (waiter.id, waiter.query.clone())).collect()
+    }
+
+    /// Marks a parked waiter as served by a take about to be proposed, so [`TakeWaiters::cancel`]
+    /// leaves it. `false` if it was cancelled meanwhile.
+    fn claim(&self, id: u64) -> bool {
+        let mut queue = self.queue.lock().unwrap();
+        match queue.iter_mut().find(|waiter| waiter.id == id) {
+            Some(waiter) if waiter.state == WaiterState::Parked => {
+                waiter.state = WaiterState::Claimed;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Answers a claimed waiter with the tuple its take committed. Returns the tuple if nobody
+    /// receives it any more.
+    fn hand_over(&self, id: u64, tuple: Tuple) -> Option<Tuple> {
+        match self.remove(id) {
+            Some(waiter) => waiter.reply.send(Ok(tuple)).err().and_then(Result::ok),
+            None => Some(tuple),
+        }
+    }
+
+    /// Parks a claimed waiter again, its take found no match, or drops it if it was cancelled
+    /// meanwhile.
+    fn release(&self, id: u64) {
+        let mut queue = self.queue.lock().unwrap();
+        let Some(position) = queue.iter().position(|waiter| waiter.id == id) else { return };
+        match queue[position].state {
+            WaiterState::Parked => {}
+            WaiterState::Claimed => queue[position].state = WaiterState::Parked,
+            WaiterState::Cancelled => {
+                queue.remove(position);
+            }
+        }
+    }
+
+    fn remove(&self, id: u64) -> Option<Waiter> {
+        let mut queue = self.queue.lock().unwrap();
+        let position = queue.iter().position(|waiter| waiter.id == id)?;
+        queue.remove(position)
+    }
+
+    fn fail_all(&self, leader: &typ::ForwardToLeader) {
+        for waiter in self.queue.lock().unwrap().drain(..) {
+            let _ = waiter.reply.send(Err(leader.clone()));
+        }
+    }
+}
+
+/// Spawns the task that serves parked takes while this node is the leader. After every batch of
+/// applied requests it walks the waiters oldest first and, for each whose query matches a local
+/// tuple, proposes a `Request::Get` on its behalf, so takes go through the log like any other and
+/// the oldest matching waiter is served first. A waiter stays claimed while its take is proposed,
+/// so the tuple is never taken for a request that gave up. When this node loses leadership every
+/// waiter is answered with the new leader to retry with.
+pub fn spawn_dispatcher(
+    raft: Raft,
+    node_id: NodeId,
+    state_machine_store: Arc<StateMachineStore>,
+    waiters: Arc<TakeWaiters>,
+) -> tokio::task::JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut metrics = raft.metrics();
+        loop {
+            tokio::select! {
+                _ = state_machine_store.applied() => {},
+                _ = waiters.parked.notified() => {},
+                changed = metrics.changed() => {
+                    if changed.is_err() {
+                        // Raft shut down.
+                        break;
+                    }
+                },
+            }
+            let current_metrics = metrics.borrow().clone();
+            if current_metrics.current_leader != Some(node_id) {
+                waiters.fail_all(&forward_to_leader(&current_metrics));
+                continue;
+            }
+            dispatch(&raft, &state_machine_store, &waiters).await;
+        }
+    })
+}
+
+async fn dispatch(raft: &Raft, state_machine_store: &StateMachineStore, waiters: &TakeWaiters) {
+    for (id, query) in waiters.pending() {
+        let has_match = {
+            let state_machine = state_machine_store.state_machine.read().await;
+            matches!(state_machine.data.read(&query), Ok(Some(_)))
+        };
+        if !has_match || !waiters.claim(id) {
+            continue;
+        }
+        let tuple = match raft.client_write(Request::Get { query }).await {
+            Ok(response) => response.data.value,
+            Err(error) => {
+                // Most likely lost leadership; the dispatcher loop answers the waiters.
+                Logger::warn(format!("blocking take not committed: {:?}", error), true);
+                waiters.release(id);
+                return;
+            }
+        };
+        match tuple {
+            Some(tuple) => {
+                // Only if the client went away, like any response lost on the way.
+                if let Some(tuple) = waiters.hand_over(id, tuple) {
+                    Logger::warn(format!("tuple of a blocking take not received: {:?}", tuple), true);
+                }
+            }
+            // An earlier waiter's take, or any other request, got the match first.
+            None => waiters.release(id),
+        }
+    }
+}
+
+#[test]
+fn test_take_waiters_claim() {
+    let waiters = TakeWaiters::default();
+    let (first, mut first_reply) = waiters.park(QueryTuple::builder().string("job").build());
+    let (second, mut second_reply) = waiters.park(QueryTuple::builder().string("job").build());
+    assert_eq!(vec![first, second], waiters.pending().into_iter().map(|(id, _)| id).collect::<Vec<_>>());
+
+    // Once its take is proposed, a waiter is no longer pending and its request can't give up.
+    assert!(waiters.claim(first));
+    assert_eq!(vec![second], waiters.pending().into_iter().map(|(id, _)| id).collect::<Vec<_>>());
+    assert!(!waiters.cancel(first));
+    let tuple = Tuple::builder().string("job").build();
+    assert_eq!(None, waiters.hand_over(first, tuple.clone()));
+    assert_eq!(tuple, first_reply.try_recv().unwrap().unwrap());
+
+    // A parked waiter is dropped, so no take is proposed for it.
+    assert!(waiters.cancel(second));
+    assert!(!waiters.claim(second));
+    assert!(waiters.pending().is_empty());
+    assert!(second_reply.try_recv().is_err());
+}
+
+#[test]
+fn test_take_waiters_release() {
+    let waiters = TakeWaiters::default();
+    let (id, mut reply) = waiters.park(QueryTuple::builder().any_integer().build());
+
+    // A take that found no match parks the waiter again.
+    assert!(waiters.claim(id));
+    waiters.release(id);
+    assert_eq!(1, waiters.pending().len());
+
+    // Unless its request timed out meanwhile.
+    assert!(waiters.claim(id));
+    assert!(!waiters.cancel(id));
+    waiters.release(id);
+    assert!(waiters.pending().is_empty());
+    assert!(!waiters.claim(id));
+    assert!(reply.try_recv().is_err());
+
+    // A tuple nobody receives any more is returned.
+    let (id, reply) = waiters.park(QueryTuple::builder().any_integer().build());
+    drop(reply);
+    assert!(waiters.claim(id));
+    let tuple = Tuple::builder().integer(3).build();
+    assert_eq!(Some(tuple.clone()), waiters.hand_over(id, tuple));
+}
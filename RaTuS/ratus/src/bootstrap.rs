@@ -0,0 +1,139 @@
+use std::{collections::{BTreeMap, BTreeSet}, fs, io, path::Path, time::Duration};
+
+use openraft::{BasicNode, RaftMetrics};
+use serde::{Deserialize, Serialize};
+
+use system::Logger;
+
+use crate::{NodeId, Raft, TypeConfig};
+
+/// How often the bootstrapping node checks whether the cluster can be initialized.
+pub const PROBE_INTERVAL: Duration = Duration::from_millis(1_000);
+
+/// How long to wait for a peer to answer a probe.
+const PROBE_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// The nodes a cluster is made of, read from a TOML manifest shared by all of them:
+///
+/// ```toml
+/// [[nodes]]
+/// id = 1
+/// addr = "127.0.0.1:21001"
+///
+/// [[nodes]]
+/// id = 2
+/// addr = "127.0.0.1:21002"
+/// ```
+///
+/// Every node listed starts as a voter.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ClusterConfig {
+    pub nodes: Vec<ClusterNode>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ClusterNode {
+    pub id: NodeId,
+    pub addr: String,
+}
+
+impl ClusterConfig {
+    /// Reads the manifest at `path`. Fails if it lists no node or a node id twice.
+    pub fn load(path: impl AsRef<Path>) -> io::Result<Self> {
+        let content = fs::read_to_string(path)?;
+        let config: ClusterConfig = toml::from_str(&content).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+
+        let ids: BTreeSet<NodeId> = config.nodes.iter().map(|node| node.id).collect();
+        if ids.is_empty() {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "cluster config lists no node"));
+        }
+        if ids.len() != config.nodes.len() {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "cluster config lists a node id more than once"));
+        }
+        Ok(config)
+    }
+
+    pub fn node(&self, id: NodeId) -> Option<&ClusterNode> {
+        self.nodes.iter().find(|node| node.id == id)
+    }
+
+    /// The node that initializes the cluster: the one with the lowest id.
+    pub fn bootstrapper(&self) -> Option<NodeId> {
+        self.nodes.iter().map(|node| node.id).min()
+    }
+
+    /// How many nodes must be reachable before initializing: a majority of them.
+    pub fn quorum(&self) -> usize {
+        self.nodes.len() / 2 + 1
+    }
+
+    pub fn members(&self) -> BTreeMap<NodeId, BasicNode> {
+        self.nodes.iter().map(|node| (node.id, BasicNode { addr: node.addr.clone() })).collect()
+    }
+}
+
+/// Spawns the task that initializes the cluster of `config` with every node in it as a voter.
+/// Only the lowest-id node does so, once a quorum of the nodes, itself included, answers; the
+/// others get the membership replicated from it.
+///
+/// Idempotent: nothing is done if this node is already initialized, e.g. restarted from its data
+/// directory, or if any peer already belongs to a cluster, e.g. when this node was restarted
+/// without one.
+pub fn spawn_bootstrap(raft: Raft, node_id: NodeId, config: ClusterConfig, interval: Duration) -> tokio::task::JoinHandle<()> {
+    tokio::spawn(async move {
+        if config.bootstrapper() != Some(node_id) {
+            return;
+        }
+        let client = match reqwest::Client::builder().timeout(PROBE_TIMEOUT).build() {
+            Ok(client) => client,
+            Err(error) => {
+                Logger::error(format!("cluster bootstrap not started: {:?}", error), true);
+                return;
+            }
+        };
+
+        let mut probes = tokio::time::interval(interval);
+        loop {
+            probes.tick().await;
+            match raft.is_initialized().await {
+                Ok(false) => {}
+                Ok(true) => break,
+                Err(error) => {
+                    Logger::error(format!("cluster bootstrap stopped: {:?}", error), true);
+                    break;
+                }
+            }
+
+            let mut reachable = 1;
+            let mut joined = false;
+            for peer in config.nodes.iter().filter(|node| node.id != node_id) {
+                if let Some(metrics) = probe(&client, &peer.addr).await {
+                    reachable += 1;
+                    joined |= metrics.membership_config.membership().voter_ids().next().is_some();
+                }
+            }
+            if joined {
+                Logger::info("cluster already initialized by a peer, waiting to be contacted by its leader", true);
+                break;
+            }
+            if reachable < config.quorum() {
+                Logger::info(format!("cluster bootstrap waiting for peers: {} of {} nodes reachable, {} needed", reachable, config.nodes.len(), config.quorum()), true);
+                continue;
+            }
+
+            match raft.initialize(config.members()).await {
+                Ok(()) => Logger::info(format!("cluster initialized with nodes {:?}", config.members().keys()), true),
+                // E.g. initialized meanwhile, by a request to `/init`.
+                Err(error) => Logger::warn(format!("cluster not initialized: {:?}", error), true),
+            }
+            break;
+        }
+    })
+}
+
+/// The metrics of the node at `addr`, `None` if it does not answer.
+async fn probe(client: &reqwest::Client, addr: &str) -> Option<RaftMetrics<TypeConfig>> {
+    let resp = client.get(format!("http://{}/metrics", addr)).send().await.ok()?;
+    let metrics: Result<RaftMetrics<TypeConfig>, openraft::error::Infallible> = resp.json().await.ok()?;
+    metrics.ok()
+}
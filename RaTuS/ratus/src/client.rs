@@ -1,12 +1,27 @@
 use core::result::Result::Ok;
-use std::{ collections::BTreeSet, sync::{ Arc, Mutex }, time::Duration };
+use std::{ collections::{ hash_map::RandomState, BTreeSet }, hash::BuildHasher, io, sync::{ Arc, Mutex }, time::{ Duration, Instant, SystemTime } };
 use serde::{ de::DeserializeOwned, Serialize };
 use tokio::time::timeout;
 use ts_core::tuple::Tuple;
 
-use openraft::{ error::{ ForwardToLeader, NetworkError, RemoteError }, RaftMetrics, TryAsRef };
+use openraft::{ error::{ ForwardToLeader, NetworkError, RemoteError, Unreachable }, RaftMetrics, TryAsRef };
 
-use crate::{ api::ReadRequest, typ, NodeId, Request, TypeConfig };
+use crate::{ api::{ ReadAllRequest, ReadRequest, TakeRequest }, store::Response, typ, NodeId, Request, TypeConfig };
+
+/// How long to wait for a response, past the server-side timeout of a blocking take.
+const RPC_TIMEOUT: Duration = Duration::from_millis(3_000);
+
+/// How many times a request of the session is sent when no response comes back.
+const SESSION_ATTEMPTS: usize = 3;
+
+/// How long to wait before sending a request of the session again.
+const SESSION_RETRY_INTERVAL: Duration = Duration::from_millis(500);
+
+/// How long [`RaftClusterClient::transfer_leader`] waits for the target to report itself leader.
+const LEADER_TRANSFER_TIMEOUT: Duration = Duration::from_millis(10_000);
+
+/// How often [`RaftClusterClient::transfer_leader`] asks the target who the leader is.
+const LEADER_POLL_INTERVAL: Duration = Duration::from_millis(100);
 
 #[derive(Clone, Debug)]
 pub struct Node {
@@ -30,6 +45,11 @@ pub struct RaftClusterClient<'a> {
     leader: Arc<Mutex<Node>>,
     members: Vec<&'a Node>,
     inner: reqwest::Client,
+    /// Identifies this client's session with the state machine.
+    client_id: u64,
+    /// Sequence number of the last request sent in the session. Locked while a request is in
+    /// flight, so requests reach the state machine one at a time and in order.
+    session_seq: tokio::sync::Mutex<u64>,
 }
 
 impl<'a> RaftClusterClient<'a> {
@@ -40,6 +60,8 @@ impl<'a> RaftClusterClient<'a> {
             leader: Arc::new(Mutex::new(leader_node.clone())),
             inner: reqwest::Client::new(),
             members,
+            client_id: RandomState::new().hash_one(SystemTime::now()),
+            session_seq: tokio::sync::Mutex::new(0),
         }
     }
 
@@ -48,24 +70,37 @@ impl<'a> RaftClusterClient<'a> {
         *t = node.clone();
     }
 
+    /// Identifies this client's session with the state machine, the `client_id` of the
+    /// `Request::Session` it sends.
+    pub fn session_id(&self) -> u64 {
+        self.client_id
+    }
+
+    /// Sequence number of the last request sent in the session. Waits for a request in flight.
+    pub async fn last_seq(&self) -> u64 {
+        *self.session_seq.lock().await
+    }
+
     /// Submit a write request to the raft cluster.
     ///
     /// The request will be processed by raft protocol: it will be replicated to a quorum and then
     /// will be applied to state machine.
     ///
-    /// The result of applying the request will be returned.
+    /// The result of applying the request will be returned. The request is sent in this client's
+    /// session, so it is applied once even if it reaches the leader more than once.
     pub async fn write(
         &self,
         req: &Request
     ) -> Result<typ::ClientWriteResponse, typ::RPCError<typ::ClientWriteError>> {
-        self.send_rpc_to_leader("write", Some(req)).await
+        self.send_in_session("write", req).await
     }
 
-    /// Read value by key, in an inconsistent mode.
+    /// Read a tuple matching the query, as up to date as `req.consistency` asks for.
     ///
-    /// This method may return stale value because it does not force to read on a legal leader.
-    pub async fn read(&self, req: &ReadRequest) -> Result<Option<Tuple>, typ::RPCError> {
-        let val = self.do_send_rpc_to_leader("read", Some(req)).await;
+    /// A `Stale` read may return a stale value because it does not force to read on a legal
+    /// leader. Other reads follow `ForwardToLeader` errors to the leader.
+    pub async fn read(&self, req: &ReadRequest) -> Result<Option<Tuple>, typ::RPCError<typ::CheckIsLeaderError>> {
+        let val = self.send_rpc_to_leader("read", Some(req)).await;
         return val;
     }
 
@@ -80,6 +115,65 @@ impl<'a> RaftClusterClient<'a> {
         return None;
     }
 
+    /// Remove every tuple matching a `Request::GetAll` with a single log entry.
+    ///
+    /// Returns the removed tuples, or an empty vec if the request failed.
+    pub async fn get_all(
+        &self,
+        req: &Request
+    ) -> Vec<Tuple> {
+        match self._get(req).await {
+            Ok(res) => res.data.values,
+            Err(_) => Vec::new(),
+        }
+    }
+
+    /// Atomically take one tuple matching a `Request::Replace` and write its replacement, with a
+    /// single log entry.
+    ///
+    /// Returns the taken tuple, or `None` if nothing matched or the request failed.
+    pub async fn replace(
+        &self,
+        req: &Request
+    ) -> Option<Tuple> {
+        match self._get(req).await {
+            Ok(res) => res.data.value,
+            Err(_) => None,
+        }
+    }
+
+    /// Apply a `Request::Transaction` with a single log entry.
+    ///
+    /// Returns the per-op results, or the index of the op that aborted it, in the response;
+    /// `None` if the request failed.
+    pub async fn transaction(
+        &self,
+        req: &Request
+    ) -> Option<Response> {
+        match self._get(req).await {
+            Ok(res) => Some(res.data),
+            Err(_) => None,
+        }
+    }
+
+    /// Take a tuple matching the query, waiting up to `req.timeout_ms` for one to be written.
+    ///
+    /// The leader serves blocking takes in the order they arrive. Returns `None` on timeout.
+    pub async fn take(&self, req: &TakeRequest) -> Result<Option<Tuple>, typ::RPCError<typ::ClientWriteError>> {
+        let timeout = Duration::from_millis(req.timeout_ms) + RPC_TIMEOUT;
+        self.send_rpc_to_leader_within("take", Some(req), timeout).await
+    }
+
+    /// Read all tuples matching the query, as up to date as `req.consistency` asks for.
+    pub async fn read_all(&self, req: &ReadAllRequest) -> Result<Vec<Tuple>, typ::RPCError<typ::CheckIsLeaderError>> {
+        self.send_rpc_to_leader("read-all", Some(req)).await
+    }
+
+    /// Count the tuples matching the query, as up to date as `req.consistency` asks for.
+    pub async fn count(&self, req: &ReadRequest) -> Result<usize, typ::RPCError<typ::CheckIsLeaderError>> {
+        self.send_rpc_to_leader("count", Some(req)).await
+    }
+
     /// Submit a write request to the raft cluster.
     ///
     /// The request will be processed by raft protocol: it will be replicated to a quorum and then
@@ -90,10 +184,40 @@ impl<'a> RaftClusterClient<'a> {
         &self,
         req: &Request
     ) -> Result<typ::ClientWriteResponse, typ::RPCError<typ::ClientWriteError>> {
-        let vars = self.send_rpc_to_leader("get", Some(req)).await;
+        let vars = self.send_in_session("get", req).await;
         return vars;
     }
 
+    /// Send `req` as the next request of this client's session. A `Request::Session` is sent as
+    /// is, e.g. one kept by the caller to retry it after a timeout.
+    ///
+    /// Sent again, with the same sequence number, up to [`SESSION_ATTEMPTS`] times when it times
+    /// out or the connection fails: the state machine applies it once either way.
+    async fn send_in_session(
+        &self,
+        uri: &str,
+        req: &Request
+    ) -> Result<typ::ClientWriteResponse, typ::RPCError<typ::ClientWriteError>> {
+        let mut seq = self.session_seq.lock().await;
+        let req = match req {
+            Request::Session { .. } => req.clone(),
+            _ => {
+                *seq += 1;
+                Request::Session { client_id: self.client_id, seq: *seq, request: Box::new(req.clone()) }
+            }
+        };
+        let mut attempts = SESSION_ATTEMPTS;
+        loop {
+            match self.send_rpc_to_leader(uri, Some(&req)).await {
+                Err(typ::RPCError::Unreachable(_)) if attempts > 1 => {
+                    attempts -= 1;
+                    tokio::time::sleep(SESSION_RETRY_INTERVAL).await;
+                }
+                res => return res,
+            }
+        }
+    }
+
     /// Initialize a cluster of only the node that receives this request.
     ///
     /// This is the first step to initialize a cluster.
@@ -156,6 +280,68 @@ impl<'a> RaftClusterClient<'a> {
         self.send_rpc_to_leader("change-membership", Some(req)).await
     }
 
+    /// Decommission a node: demote it to learner, wait for that membership to be committed, then
+    /// drop it from the cluster. If it is the leader, leadership first moves to another voter.
+    ///
+    /// The removed node keeps running until it is shut down.
+    pub async fn remove_node(
+        &mut self,
+        node_id: NodeId
+    ) -> Result<typ::ClientWriteResponse, typ::RPCError<typ::ClientWriteError>> {
+        let res = self.send_rpc_to_leader("remove-node", Some(&node_id)).await;
+        if res.is_ok() {
+            self.members.retain(|member| member.id != node_id);
+        }
+        res
+    }
+
+    /// Ask `node` itself to leave the cluster, as [`Self::remove_node`] does for it.
+    ///
+    /// The node hands off leadership if it has it, then has the leader remove it. A
+    /// `ForwardToLeader` error names the leader to send [`Self::remove_node`] to instead.
+    pub async fn leave(
+        &mut self,
+        node: &Node
+    ) -> Result<typ::ClientWriteResponse, typ::RPCError<typ::ClientWriteError>> {
+        // Leaves time for leadership to move and for two membership changes.
+        let res = self.do_send_rpc_to_node_within(node, "leave", Some(&()), RPC_TIMEOUT * 3).await;
+        if res.is_ok() {
+            self.members.retain(|member| member.id != node.id);
+        }
+        res
+    }
+
+    /// Move leadership to voter `target`, e.g. before restarting the current leader.
+    ///
+    /// The leader hands over to `target`, which then runs an election. Returns once `target`
+    /// reports itself as leader, from then on the target of this client. Fails at once if
+    /// `target` is not a voter, and if it does not become leader within 10 seconds, e.g. because
+    /// another node won the election.
+    pub async fn transfer_leader(&self, target: &Node) -> Result<(), typ::RPCError<typ::CheckIsLeaderError>> {
+        let leader: Option<NodeId> = self.send_rpc_to_leader("transfer-leader", Some(&target.id)).await?;
+        if leader == Some(target.id) {
+            *self.leader.lock().unwrap() = target.clone();
+            return Ok(());
+        }
+
+        let deadline = Instant::now() + LEADER_TRANSFER_TIMEOUT;
+        loop {
+            let metrics: Result<RaftMetrics<TypeConfig>, typ::RPCError> =
+                self.do_send_rpc_to_node_within(target, "metrics", None::<&()>, RPC_TIMEOUT).await;
+            if let Ok(metrics) = metrics {
+                if metrics.current_leader == Some(target.id) {
+                    *self.leader.lock().unwrap() = target.clone();
+                    return Ok(());
+                }
+            }
+            if Instant::now() >= deadline {
+                let error = io::Error::new(io::ErrorKind::TimedOut, format!("node {} did not become leader", target.id));
+                return Err(typ::RPCError::Network(NetworkError::new(&error)));
+            }
+            tokio::time::sleep(LEADER_POLL_INTERVAL).await;
+        }
+    }
+
     /// Get the metrics about the cluster.
     ///
     /// Metrics contains various information about the cluster, such as current leader,
@@ -165,6 +351,17 @@ impl<'a> RaftClusterClient<'a> {
         self.do_send_rpc_to_leader("metrics", None::<&()>).await
     }
 
+    /// Snapshot the state machine of the current target node now, purging the log it covers.
+    pub async fn trigger_snapshot(&self) -> Result<(), typ::RPCError> {
+        self.do_send_rpc_to_leader("snapshot", Some(&())).await
+    }
+
+    /// Purge the log of the current target node up to and including `upto`, as far as it is
+    /// covered by a snapshot.
+    pub async fn purge_log(&self, upto: u64) -> Result<(), typ::RPCError> {
+        self.do_send_rpc_to_leader("purge-log", Some(&upto)).await
+    }
+
     /// Send RPC to specified node.
     ///
     /// It sends out a POST request if `req` is Some. Otherwise a GET request.
@@ -182,11 +379,42 @@ impl<'a> RaftClusterClient<'a> {
             Resp: Serialize + DeserializeOwned,
             Err: std::error::Error + Serialize + DeserializeOwned
     {
-        let (leader_id, url) = {
-            let t = self.leader.lock().unwrap();
-            let target_addr = &t.addr;
-            (t.id, format!("http://{}/{}", target_addr, uri))
-        };
+        self.do_send_rpc_to_leader_within(uri, req, RPC_TIMEOUT).await
+    }
+
+    /// [`Self::do_send_rpc_to_leader`], waiting up to `timeout_after` for the response.
+    async fn do_send_rpc_to_leader_within<Req, Resp, Err>(
+        &self,
+        uri: &str,
+        req: Option<&Req>,
+        timeout_after: Duration
+    )
+        -> Result<Resp, typ::RPCError<Err>>
+        where
+            Req: Serialize + 'static,
+            Resp: Serialize + DeserializeOwned,
+            Err: std::error::Error + Serialize + DeserializeOwned
+    {
+        let leader = self.leader.lock().unwrap().clone();
+        self.do_send_rpc_to_node_within(&leader, uri, req, timeout_after).await
+    }
+
+    /// Send RPC to `target`, whether or not it is the leader, waiting up to `timeout_after` for
+    /// the response.
+    async fn do_send_rpc_to_node_within<Req, Resp, Err>(
+        &self,
+        target: &Node,
+        uri: &str,
+        req: Option<&Req>,
+        timeout_after: Duration
+    )
+        -> Result<Resp, typ::RPCError<Err>>
+        where
+            Req: Serialize + 'static,
+            Resp: Serialize + DeserializeOwned,
+            Err: std::error::Error + Serialize + DeserializeOwned
+    {
+        let url = format!("http://{}/{}", target.addr, uri);
 
         let fu = (
             if let Some(r) = req {
@@ -196,19 +424,32 @@ impl<'a> RaftClusterClient<'a> {
             }
         ).send();
 
-        let res = timeout(Duration::from_millis(3_000), fu).await;
+        // No response at all is `Unreachable`, the only error worth sending the request again for.
+        let res = timeout(timeout_after, fu).await;
         let resp = match res {
-            Ok(x) => x.map_err(|e| typ::RPCError::Network(NetworkError::new(&e)))?,
+            Ok(Ok(resp)) => resp,
+            Ok(Err(e)) if e.is_connect() || e.is_timeout() => {
+                return Err(typ::RPCError::Unreachable(Unreachable::new(&e)));
+            }
+            Ok(Err(e)) => return Err(typ::RPCError::Network(NetworkError::new(&e))),
             Err(timeout_err) => {
-                return Err(typ::RPCError::Network(NetworkError::new(&timeout_err)));
+                return Err(typ::RPCError::Unreachable(Unreachable::new(&timeout_err)));
             }
         };
 
+        // A request the node rejected, e.g. a `transfer-leader` to a node that is not a voter.
+        let status = resp.status();
+        if status.is_client_error() {
+            let message = resp.text().await.unwrap_or_default();
+            let error = io::Error::new(io::ErrorKind::InvalidInput, format!("{} answered {}: {}", url, status, message));
+            return Err(typ::RPCError::Network(NetworkError::new(&error)));
+        }
+
         let res: Result<Resp, typ::RaftError<Err>> = resp
             .json().await
             .map_err(|e| typ::RPCError::Network(NetworkError::new(&e)))?;
 
-        res.map_err(|e| typ::RPCError::RemoteError(RemoteError::new(leader_id, e)))
+        res.map_err(|e| typ::RPCError::RemoteError(RemoteError::new(target.id, e)))
     }
 
     /// Try the best to send a request to the leader.
@@ -229,12 +470,32 @@ impl<'a> RaftClusterClient<'a> {
                 DeserializeOwned +
                 TryAsRef<typ::ForwardToLeader> +
                 Clone
+    {
+        self.send_rpc_to_leader_within(uri, req, RPC_TIMEOUT).await
+    }
+
+    /// [`Self::send_rpc_to_leader`], waiting up to `timeout_after` for each response.
+    async fn send_rpc_to_leader_within<Req, Resp, Err>(
+        &self,
+        uri: &str,
+        req: Option<&Req>,
+        timeout_after: Duration
+    )
+        -> Result<Resp, typ::RPCError<Err>>
+        where
+            Req: Serialize + 'static,
+            Resp: Serialize + DeserializeOwned,
+            Err: std::error::Error +
+                Serialize +
+                DeserializeOwned +
+                TryAsRef<typ::ForwardToLeader> +
+                Clone
     {
         // Retry at most 3 times to find a valid leader.
         let mut n_retry = 3;
 
         loop {
-            let res: Result<Resp, typ::RPCError<Err>> = self.do_send_rpc_to_leader(uri, req).await;
+            let res: Result<Resp, typ::RPCError<Err>> = self.do_send_rpc_to_leader_within(uri, req, timeout_after).await;
 
             let rpc_err = match res {
                 Ok(x) => {
@@ -0,0 +1,45 @@
+use std::{sync::Arc, time::Duration};
+
+use system::Logger;
+
+use crate::{NodeId, Raft, StateMachineStore};
+
+/// How often the size of the log beyond the last snapshot is checked.
+pub const CHECK_INTERVAL: Duration = Duration::from_millis(1_000);
+
+/// Spawns the task that snapshots this node once the requests applied since its last snapshot
+/// add up to `max_log_bytes`. Openraft then purges the log that snapshot covers, keeping
+/// `max_in_snapshot_log_to_keep` entries.
+///
+/// No new snapshot is triggered while the last one triggered is still being built, and a failed
+/// trigger is retried at the next check.
+pub fn spawn_size_trigger(
+    raft: Raft,
+    node_id: NodeId,
+    state_machine_store: Arc<StateMachineStore>,
+    max_log_bytes: u64,
+    interval: Duration,
+) -> tokio::task::JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut checks = tokio::time::interval(interval);
+        // The last snapshot as of the trigger, until a newer one shows up in the metrics.
+        let mut pending = None;
+        loop {
+            checks.tick().await;
+            let last_snapshot = raft.metrics().borrow().snapshot;
+            if pending.is_some_and(|triggered_after| triggered_after == last_snapshot) {
+                continue;
+            }
+            pending = None;
+            let log_bytes = state_machine_store.applied_bytes_since_snapshot();
+            if log_bytes < max_log_bytes {
+                continue;
+            }
+            Logger::info(format!("node {} snapshotting after {} bytes of log", node_id, log_bytes), true);
+            match raft.trigger().snapshot().await {
+                Ok(()) => pending = Some(last_snapshot),
+                Err(error) => Logger::error(format!("snapshot not triggered: {:?}", error), true),
+            }
+        }
+    })
+}
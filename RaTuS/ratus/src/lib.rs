@@ -1,18 +1,24 @@
-use std::{io::Cursor, sync::Arc};
+use std::{path::PathBuf, sync::Arc};
 
-use actix_web::{middleware::{self, Logger}, web::Data, HttpServer};
-use openraft::Config;
+use actix_web::{middleware::{self, Logger}, web::{Data, PayloadConfig}, HttpServer};
+use openraft::{Config, SnapshotPolicy};
 
 use crate::{
     app::App, 
-    network::{api, management, raft, Network}, 
-    store::{Request, Response}
+    blocking_take::TakeWaiters, 
+    bootstrap::ClusterConfig, 
+    network::{api, management, raft, snapshot_chunk, Network}, 
+    store::{FileLogStore, Request, Response, SnapshotBuffer}
 };
 
 pub mod app;
+pub mod blocking_take;
+pub mod bootstrap;
 pub mod client;
+pub mod compaction;
 pub mod network;
 pub mod store;
+pub mod ticker;
 
 #[cfg(test)] mod test;
 
@@ -23,9 +29,11 @@ openraft::declare_raft_types!(
     pub TypeConfig:
         D = Request,
         R = Response,
+        SnapshotData = SnapshotBuffer,
 );
 
 pub type LogStore = store::LogStore;
+pub type NodeLogStore = store::NodeLogStore;
 pub type StateMachineStore = store::StateMachineStore;
 pub type Raft = openraft::Raft<TypeConfig>;
 
@@ -40,25 +48,81 @@ pub mod typ {
     pub type CheckIsLeaderError = openraft::error::CheckIsLeaderError<TypeConfig>;
     pub type ForwardToLeader = openraft::error::ForwardToLeader<TypeConfig>;
     pub type InitializeError = openraft::error::InitializeError<TypeConfig>;
+    pub type Fatal = openraft::error::Fatal<TypeConfig>;
 
     pub type ClientWriteResponse = openraft::raft::ClientWriteResponse<TypeConfig>;
 }
 
+/// How a node is run, besides its id and address.
+#[derive(Clone, Debug, Default)]
+pub struct NodeOptions {
+    /// Directory to keep the Raft log, vote, committed log id and latest snapshot in across
+    /// restarts. Kept in memory only if `None`.
+    pub data_dir: Option<PathBuf>,
+
+    /// Snapshot once this many log entries were applied since the last snapshot. Openraft's
+    /// default if `None`.
+    pub snapshot_logs_since_last: Option<u64>,
+
+    /// Also snapshot once the requests applied since the last snapshot add up to this many
+    /// bytes. No size limit if `None`.
+    pub snapshot_max_log_bytes: Option<u64>,
+
+    /// How many log entries a snapshot already covers to keep for followers that lag behind,
+    /// instead of sending them the whole snapshot. Openraft's default if `None`.
+    pub max_in_snapshot_log_to_keep: Option<u64>,
+
+    /// LZ4 compress the snapshots this node builds.
+    pub compress_snapshots: bool,
+
+    /// Largest chunk, in bytes, a snapshot is sent to a follower in. A chunk that fails is sent
+    /// again from its offset. Openraft's default if `None`.
+    pub snapshot_max_chunk_size: Option<u64>,
+
+    /// The cluster this node belongs to. Its lowest-id node initializes it once a quorum of the
+    /// nodes is up, instead of waiting for `init`.
+    pub cluster: Option<ClusterConfig>,
+}
+
 pub async fn start_example_raft_node(node_id: NodeId, http_addr: String) -> std::io::Result<()> {
+    start_raft_node(node_id, http_addr, NodeOptions::default()).await
+}
+
+pub async fn start_raft_node(node_id: NodeId, http_addr: String, options: NodeOptions) -> std::io::Result<()> {
     // Create a configuration for the raft instance.
-    let config = Config {
+    let mut config = Config {
         heartbeat_interval: 500,
         election_timeout_min: 1500,
         election_timeout_max: 3000,
         ..Default::default()
     };
+    if let Some(logs_since_last) = options.snapshot_logs_since_last {
+        config.snapshot_policy = SnapshotPolicy::LogsSinceLast(logs_since_last);
+    }
+    if let Some(max_in_snapshot_log_to_keep) = options.max_in_snapshot_log_to_keep {
+        config.max_in_snapshot_log_to_keep = max_in_snapshot_log_to_keep;
+    }
+    if let Some(snapshot_max_chunk_size) = options.snapshot_max_chunk_size {
+        config.snapshot_max_chunk_size = snapshot_max_chunk_size;
+    }
 
     let config = Arc::new(config.validate().unwrap());
 
     // Create a instance of where the Raft logs will be stored.
-    let log_store = LogStore::default();
+    let log_store = match &options.data_dir {
+        Some(data_dir) => NodeLogStore::File(FileLogStore::open(data_dir)?),
+        None => NodeLogStore::default(),
+    };
     // Create a instance of where the Raft data will be stored.
-    let state_machine_store = Arc::new(StateMachineStore::default());
+    let state_machine_store = match &options.data_dir {
+        Some(data_dir) => StateMachineStore::open(data_dir)?,
+        None => StateMachineStore::default(),
+    };
+    let state_machine_store = Arc::new(
+        state_machine_store
+            .compress_snapshots(options.compress_snapshots)
+            .measure_applied_bytes(options.snapshot_max_log_bytes.is_some()),
+    );
 
     // Create the network layer that will connect and communicate the raft instances and
     // will be used in conjunction with the store created above.
@@ -75,6 +139,30 @@ pub async fn start_example_raft_node(node_id: NodeId, http_addr: String) -> std:
     .await
     .unwrap();
 
+    // Drive tuple expiry with timestamps proposed by whichever node is the leader.
+    ticker::spawn_ticker(raft.clone(), node_id, state_machine_store.clone(), ticker::TICK_INTERVAL);
+
+    if let Some(max_log_bytes) = options.snapshot_max_log_bytes {
+        compaction::spawn_size_trigger(
+            raft.clone(),
+            node_id,
+            state_machine_store.clone(),
+            max_log_bytes,
+            compaction::CHECK_INTERVAL,
+        );
+    }
+
+    if let Some(cluster) = options.cluster {
+        bootstrap::spawn_bootstrap(raft.clone(), node_id, cluster, bootstrap::PROBE_INTERVAL);
+    }
+
+    let take_waiters = Arc::new(TakeWaiters::default());
+    blocking_take::spawn_dispatcher(raft.clone(), node_id, state_machine_store.clone(), take_waiters.clone());
+
+    // Binary bodies, i.e. snapshot chunks, must fit a whole chunk. Every node of a cluster should
+    // use the same chunk size.
+    let payload_limit = config.snapshot_max_chunk_size as usize + snapshot_chunk::MAX_HEADER_LEN;
+
     // Create an application that will store all the instances created above, this will
     // later be used on the actix-web services.
     let app_data = Data::new(App {
@@ -84,6 +172,8 @@ pub async fn start_example_raft_node(node_id: NodeId, http_addr: String) -> std:
         log_store,
         state_machine_store,
         config,
+        leader_contact: Default::default(),
+        take_waiters,
     });
 
     // Start the actix-web server.
@@ -93,6 +183,7 @@ pub async fn start_example_raft_node(node_id: NodeId, http_addr: String) -> std:
             .wrap(Logger::new("%a %{User-Agent}i"))
             .wrap(middleware::Compress::default())
             .app_data(app_data.clone())
+            .app_data(PayloadConfig::new(payload_limit))
             // raft internal RPC
             .service(raft::append)
             .service(raft::snapshot)
@@ -101,11 +192,20 @@ pub async fn start_example_raft_node(node_id: NodeId, http_addr: String) -> std:
             .service(management::init)
             .service(management::add_learner)
             .service(management::change_membership)
+            .service(management::remove_node)
+            .service(management::leave)
+            .service(management::transfer_leader)
             .service(management::metrics)
+            .service(management::snapshot)
+            .service(management::purge_log)
             // application API
             .service(api::write)
             .service(api::read)
             .service(api::get)
+            .service(api::read_all)
+            .service(api::count)
+            .service(api::take)
+            .service(api::watch)
     });
 
     let x = server.bind(http_addr)?;
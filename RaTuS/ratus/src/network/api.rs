@@ -1,16 +1,105 @@
-use actix_web::{post, web::{self, Data}, Responder};
+use std::{convert::Infallible, time::Duration};
+
+use actix_web::{error, get, http::header, post, web::{self, Bytes, Data}, HttpRequest, HttpResponse, Responder};
+use futures_util::{stream, Stream};
+use openraft::RaftMetrics;
 use serde::{Deserialize, Serialize};
 use ts_core::{query_tuple::QueryTuple, store::Store, tuple::Tuple};
 use web::Json;
 
 use system::Logger;
 
-use crate::{app::App, store::Request, typ};
+use crate::{app::App, store::{watch::{EventPosition, WatchEvent}, Request}, ticker, typ, TypeConfig};
+
+/// How up to date a read must be. A node that cannot serve the requested consistency answers
+/// with a `ForwardToLeader` error naming the leader, if it knows one.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum ReadConsistency {
+    /// Read whatever this node has applied, possibly behind the leader.
+    #[default]
+    Stale,
+    /// Read on the node that believes it is the leader, without checking that it still is: a
+    /// deposed leader may serve stale tuples until it learns of the new term.
+    Leader,
+    /// Read on the leader after confirming its leadership with a quorum and applying the log
+    /// up to the read index, so the read reflects every write committed before it.
+    Linearizable,
+    /// Read on a follower only if it is within the given bounds of the leader, on the leader
+    /// otherwise. `max_lag_entries` bounds how far the applied log is behind the leader's commit
+    /// index as of the last append or heartbeat from it, `max_lag_ms` how long ago that was, so it
+    /// should exceed the heartbeat interval. An unset bound is not checked.
+    Bounded {
+        max_lag_entries: Option<u64>,
+        max_lag_ms: Option<u64>,
+    },
+}
+
+/// Returns once this node may serve a read with `consistency`.
+async fn ensure_consistency(app: &App, consistency: ReadConsistency) -> Result<(), typ::RaftError<typ::CheckIsLeaderError>> {
+    match consistency {
+        ReadConsistency::Stale => Ok(()),
+        ReadConsistency::Leader => {
+            let metrics = app.raft.metrics().borrow().clone();
+            if metrics.current_leader == Some(app.id) {
+                Ok(())
+            } else {
+                Err(typ::RaftError::APIError(typ::CheckIsLeaderError::ForwardToLeader(forward_to_leader(&metrics))))
+            }
+        }
+        ReadConsistency::Linearizable => app.raft.ensure_linearizable().await.map(|_| ()),
+        ReadConsistency::Bounded { max_lag_entries, max_lag_ms } => {
+            let metrics = app.raft.metrics().borrow().clone();
+            if metrics.current_leader == Some(app.id) {
+                return Ok(());
+            }
+            let applied = app.state_machine_store.state_machine.read().await.last_applied_log.map(|log_id| log_id.index);
+            let within_bounds = match app.leader_contact() {
+                Some(contact) => {
+                    let lag_entries = match (contact.committed, applied) {
+                        (Some(committed), Some(applied)) => committed.saturating_sub(applied),
+                        (Some(committed), None) => committed + 1,
+                        (None, _) => 0,
+                    };
+                    max_lag_entries.is_none_or(|max| lag_entries <= max)
+                        && max_lag_ms.is_none_or(|max| contact.at.elapsed() <= Duration::from_millis(max))
+                }
+                // Never heard from a leader, so the lag is unknown.
+                None => max_lag_entries.is_none() && max_lag_ms.is_none(),
+            };
+            if within_bounds {
+                Ok(())
+            } else {
+                Err(typ::RaftError::APIError(typ::CheckIsLeaderError::ForwardToLeader(forward_to_leader(&metrics))))
+            }
+        }
+    }
+}
+
+/// Where to send a request this node cannot serve: the leader it knows of, if any.
+pub(crate) fn forward_to_leader(metrics: &RaftMetrics<TypeConfig>) -> typ::ForwardToLeader {
+    let leader_id = metrics.current_leader;
+    let leader_node = leader_id.and_then(|id| metrics.membership_config.membership().get_node(&id).cloned());
+    typ::ForwardToLeader { leader_id, leader_node }
+}
+
+/// Proposes a request sent by a client. `Tick`s are rejected with `400 Bad Request`: only the
+/// leader's ticker moves the clock tuples expire by.
+async fn propose(app: &App, req: Request) -> actix_web::Result<Result<typ::ClientWriteResponse, typ::RaftError<typ::ClientWriteError>>> {
+    if req.is_tick() {
+        return Err(error::ErrorBadRequest("Tick requests are proposed by the leader only"));
+    }
+    // A follower answers with `ForwardToLeader` below, and the leader refreshes the clock there.
+    if req.reads_clock() && app.raft.metrics().borrow().current_leader == Some(app.id) {
+        ticker::refresh_clock(&app.raft, &app.state_machine_store, ticker::TICK_INTERVAL).await;
+    }
+    Ok(app.raft.client_write(req).await)
+}
 
 #[post("/write")]
 pub async fn write(app: Data<App>, req: Json<Request>) -> actix_web::Result<impl Responder> {
     Logger::info(format!("write request: {:?}", req.0), true);
-    let response = app.raft.client_write(req.0).await;
+    let response = propose(&app, req.0).await?;
     Logger::info(format!("write response: {:?}", response), true);
     Ok(Json(response))
 }
@@ -18,21 +107,64 @@ pub async fn write(app: Data<App>, req: Json<Request>) -> actix_web::Result<impl
 #[post("/get")]
 pub async fn get(app: Data<App>, req: Json<Request>) -> actix_web::Result<impl Responder> {
     Logger::info(format!("get request: {:?}", req.0), true);
-    let response = app.raft.client_write(req.0).await;
+    let response = propose(&app, req.0).await?;
     Logger::info(format!("get response: {:?}", response), true);
     Ok(Json(response))
 }
 
+/// A take that waits up to `timeout_ms` for a match instead of returning `None` at once.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct TakeRequest {
+    pub query: QueryTuple,
+    pub timeout_ms: u64,
+}
+
+/// Blocking take, served by the leader only: parks the request until the tuple it takes is
+/// committed, see [`crate::blocking_take`]. `None` after the timeout; a `ForwardToLeader` error if
+/// this node is not, or stops being, the leader.
+#[post("/take")]
+pub async fn take(app: Data<App>, req: Json<TakeRequest>) -> actix_web::Result<impl Responder> {
+    Logger::info(format!("take request: {:?}", req.0), true);
+    let TakeRequest { query, timeout_ms } = req.0;
+    let metrics = app.raft.metrics().borrow().clone();
+    let res: Result<Option<Tuple>, typ::RaftError<typ::ClientWriteError>> = if metrics.current_leader != Some(app.id) {
+        Err(typ::RaftError::APIError(typ::ClientWriteError::ForwardToLeader(forward_to_leader(&metrics))))
+    } else {
+        let (id, mut reply) = app.take_waiters.park(query);
+        let outcome = match tokio::time::timeout(Duration::from_millis(timeout_ms), &mut reply).await {
+            Ok(outcome) => Some(outcome),
+            // A take already proposed for it is waited for, or its tuple would be lost.
+            Err(_) if !app.take_waiters.cancel(id) => Some(reply.await),
+            Err(_) => None,
+        };
+        match outcome {
+            Some(Ok(Ok(tuple))) => Ok(Some(tuple)),
+            Some(Ok(Err(leader))) => Err(typ::RaftError::APIError(typ::ClientWriteError::ForwardToLeader(leader))),
+            // Timed out, or the dispatcher is gone, Raft shut down.
+            Some(Err(_)) | None => Ok(None),
+        }
+    };
+    Logger::info(format!("take response: {:?}", res), true);
+    Ok(Json(res))
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct ReadRequest {
     pub query: QueryTuple,
+    #[serde(default)]
+    pub consistency: ReadConsistency,
 }
 
 #[post("/read")]
 pub async fn read(app: Data<App>, req: Json<ReadRequest>) -> actix_web::Result<impl Responder> {
     Logger::info(format!("read request: {:?}", req.0), true);
+    let ReadRequest { query, consistency } = req.0;
+    if let Err(e) = ensure_consistency(&app, consistency).await {
+        let total_res: Result<Option<Tuple>, _> = Err(e);
+        Logger::info(format!("read response: {:?}", total_res), true);
+        return Ok(Json(total_res));
+    }
     let state_machine = app.state_machine_store.state_machine.read().await;
-    let query = req.0.query;
     let value = state_machine.data.read(&query);
 
     let res: Option<Tuple> = match value {
@@ -40,7 +172,104 @@ pub async fn read(app: Data<App>, req: Json<ReadRequest>) -> actix_web::Result<i
         Err(_) => None,
     };
 
-    let total_res: Result<Option<Tuple>, typ::RPCError> = Ok(res);
+    let total_res: Result<Option<Tuple>, typ::RaftError<typ::CheckIsLeaderError>> = Ok(res);
     Logger::info(format!("read response: {:?}", total_res), true);
     Ok(Json(total_res))
 }
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ReadAllRequest {
+    pub query: QueryTuple,
+    pub limit: Option<usize>,
+    #[serde(default)]
+    pub consistency: ReadConsistency,
+}
+
+#[post("/read-all")]
+pub async fn read_all(app: Data<App>, req: Json<ReadAllRequest>) -> actix_web::Result<impl Responder> {
+    Logger::info(format!("read-all request: {:?}", req.0), true);
+    let ReadAllRequest { query, limit, consistency } = req.0;
+    if let Err(e) = ensure_consistency(&app, consistency).await {
+        let total_res: Result<Vec<Tuple>, _> = Err(e);
+        Logger::info(format!("read-all response: {:?}", total_res), true);
+        return Ok(Json(total_res));
+    }
+    let state_machine = app.state_machine_store.state_machine.read().await;
+    let res: Vec<Tuple> = state_machine.data.read_all(&query, limit).unwrap_or_default();
+
+    let total_res: Result<Vec<Tuple>, typ::RaftError<typ::CheckIsLeaderError>> = Ok(res);
+    Logger::info(format!("read-all response: {:?}", total_res), true);
+    Ok(Json(total_res))
+}
+
+#[post("/count")]
+pub async fn count(app: Data<App>, req: Json<ReadRequest>) -> actix_web::Result<impl Responder> {
+    Logger::info(format!("count request: {:?}", req.0), true);
+    let ReadRequest { query, consistency } = req.0;
+    if let Err(e) = ensure_consistency(&app, consistency).await {
+        let total_res: Result<usize, _> = Err(e);
+        Logger::info(format!("count response: {:?}", total_res), true);
+        return Ok(Json(total_res));
+    }
+    let state_machine = app.state_machine_store.state_machine.read().await;
+    let res: usize = state_machine.data.count(&query).unwrap_or_default();
+
+    let total_res: Result<usize, typ::RaftError<typ::CheckIsLeaderError>> = Ok(res);
+    Logger::info(format!("count response: {:?}", total_res), true);
+    Ok(Json(total_res))
+}
+
+/// Query string of `watch`. `query` is a `QueryTuple` in JSON.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct WatchRequest {
+    pub query: String,
+    /// Replay the kept events from this log index on before the new ones.
+    pub from_index: Option<u64>,
+}
+
+/// Streams the tuples written and taken on this node that match `query`, as Server-Sent Events
+/// named `written` or `taken` with the tuple in JSON as data and `<log index>-<ordinal>` as id. A
+/// reconnecting client resumes after the event named by its `Last-Event-ID` header, or at
+/// `from_index`; `410 Gone` if the events there are no longer kept. The stream ends if the client
+/// falls too far behind, to be resumed the same way.
+#[get("/watch")]
+pub async fn watch(app: Data<App>, req: web::Query<WatchRequest>, http_req: HttpRequest) -> actix_web::Result<HttpResponse> {
+    Logger::info(format!("watch request: {:?}", req.0), true);
+    let query: QueryTuple = serde_json::from_str(&req.query).map_err(error::ErrorBadRequest)?;
+    let last_event_id = match http_req.headers().get("Last-Event-ID") {
+        Some(value) => {
+            let position: EventPosition = value.to_str().map_err(error::ErrorBadRequest)?.parse().map_err(error::ErrorBadRequest)?;
+            Some(EventPosition { ordinal: position.ordinal.saturating_add(1), ..position })
+        }
+        None => None,
+    };
+    let from = last_event_id.or(req.from_index.map(|index| EventPosition { index, ordinal: 0 }));
+    let (replay, live) = app.state_machine_store.watch().subscribe(from)
+        .map_err(|earliest| error::ErrorGone(format!("events before {} are no longer kept", earliest)))?;
+
+    Ok(HttpResponse::Ok()
+        .content_type("text/event-stream")
+        .insert_header((header::CACHE_CONTROL, "no-cache"))
+        .streaming(watch_stream(query, replay, live)))
+}
+
+fn watch_stream(
+    query: QueryTuple,
+    replay: Vec<WatchEvent>,
+    live: tokio::sync::broadcast::Receiver<WatchEvent>,
+) -> impl Stream<Item = Result<Bytes, Infallible>> {
+    stream::unfold((query, replay.into_iter(), live), |(query, mut replay, mut live)| async move {
+        loop {
+            let event = match replay.next() {
+                Some(event) => event,
+                // Lagged or closed: end the stream, the client resumes from its last event.
+                None => live.recv().await.ok()?,
+            };
+            if query == event.tuple {
+                let data = serde_json::to_string(&event.tuple).ok()?;
+                let message = format!("id: {}\nevent: {}\ndata: {}\n\n", event.position, event.kind.name(), data);
+                return Some((Ok(Bytes::from(message)), (query, replay, live)));
+            }
+        }
+    })
+}
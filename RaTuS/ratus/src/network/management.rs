@@ -1,11 +1,17 @@
-use std::collections::{BTreeMap, BTreeSet};
+use std::{collections::{BTreeMap, BTreeSet}, time::Duration};
 
-use actix_web::{get, post, web::{Data, Json}, Responder};
-use openraft::{error::Infallible, BasicNode, RaftMetrics};
+use actix_web::{error, get, post, web::{Data, Json}, Responder};
+use openraft::{error::Infallible, BasicNode, ChangeMembers, RaftMetrics};
 
 use system::Logger;
 
-use crate::{app::App, NodeId, TypeConfig};
+use crate::{app::App, network::api::forward_to_leader, typ, NodeId, TypeConfig};
+
+/// How long a leader that is leaving waits for another voter to take over.
+const LEADER_TRANSFER_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How long a leaving follower waits for the leader to remove it, both membership changes included.
+const LEAVE_FORWARD_TIMEOUT: Duration = Duration::from_secs(10);
 
 // --- Cluster management
 
@@ -33,6 +39,139 @@ pub async fn change_membership(app: Data<App>, req: Json<BTreeSet<NodeId>>) -> a
     Ok(Json(res))
 }
 
+/// Decommission a node: demote it to learner, wait for that membership to commit, then drop it
+/// from the cluster. Served by the leader only.
+///
+/// If the node to remove is the leader itself, it first hands leadership to the most up to date
+/// other voter and answers with a `ForwardToLeader` error naming it, so the removal is retried
+/// there. The removed node is not stopped.
+#[post("/remove-node")]
+pub async fn remove_node(app: Data<App>, req: Json<NodeId>) -> actix_web::Result<impl Responder> {
+    Logger::info(format!("remove-node request: {:?}", req.0), true);
+    let res = remove(&app, req.0).await;
+    Logger::info(format!("remove-node response: {:?}", res), true);
+    Ok(Json(res))
+}
+
+/// Decommission this node, like `remove-node` with its own id but sent to the node itself: a
+/// leader first hands over leadership, then the node asks the leader to remove it.
+///
+/// A `ForwardToLeader` error means the leader could not be reached, or leadership did not move
+/// in time: the node was not removed and `remove-node` may be sent to the leader named.
+#[post("/leave")]
+pub async fn leave(app: Data<App>) -> actix_web::Result<impl Responder> {
+    Logger::info("leave request", true);
+    let res = leave_cluster(&app).await;
+    Logger::info(format!("leave response: {:?}", res), true);
+    Ok(Json(res))
+}
+
+/// Hand leadership over to voter `req`, e.g. to restart this node without an election timeout.
+/// Served by the leader only.
+///
+/// Waits until a node other than this one is leader and returns it, `None` if none is known in
+/// time. Answers `400 Bad Request` at once if `req` is not a voter, which cannot take over, and
+/// returns this node if it is `req` already.
+#[post("/transfer-leader")]
+pub async fn transfer_leader(app: Data<App>, req: Json<NodeId>) -> actix_web::Result<impl Responder> {
+    Logger::info(format!("transfer-leader request: {:?}", req.0), true);
+    let metrics = app.raft.metrics().borrow().clone();
+    if !metrics.membership_config.membership().voter_ids().any(|id| id == req.0) {
+        Logger::info(format!("transfer-leader rejected: node {} is not a voter", req.0), true);
+        return Err(error::ErrorBadRequest(format!("node {} is not a voter", req.0)));
+    }
+    let res = transfer(&app, req.0).await;
+    Logger::info(format!("transfer-leader response: {:?}", res), true);
+    Ok(Json(res))
+}
+
+async fn transfer(app: &App, to: NodeId) -> Result<Option<NodeId>, typ::RaftError<typ::CheckIsLeaderError>> {
+    let metrics = app.raft.metrics().borrow().clone();
+    if metrics.current_leader != Some(app.id) {
+        return Err(typ::RaftError::APIError(typ::CheckIsLeaderError::ForwardToLeader(forward_to_leader(&metrics))));
+    }
+    if to == app.id {
+        return Ok(Some(app.id));
+    }
+    let leader = step_down(app, to).await.map_err(typ::RaftError::Fatal)?;
+    Ok(leader.leader_id)
+}
+
+async fn remove(app: &App, node_id: NodeId) -> Result<typ::ClientWriteResponse, typ::RaftError<typ::ClientWriteError>> {
+    let metrics = app.raft.metrics().borrow().clone();
+    if metrics.current_leader != Some(app.id) {
+        return Err(typ::RaftError::APIError(typ::ClientWriteError::ForwardToLeader(forward_to_leader(&metrics))));
+    }
+    if node_id == app.id {
+        // The last voter cannot leave: changing the membership below fails as it would be empty.
+        if let Some(successor) = successor(&metrics) {
+            let leader = step_down(app, successor).await.map_err(typ::RaftError::Fatal)?;
+            return Err(typ::RaftError::APIError(typ::ClientWriteError::ForwardToLeader(leader)));
+        }
+    }
+
+    let is_voter = metrics.membership_config.membership().voter_ids().any(|id| id == node_id);
+    if is_voter {
+        // Keep it as a learner until the membership without it as a voter is committed, so the
+        // quorum never counts on a node that is going away.
+        app.raft.change_membership(ChangeMembers::RemoveVoters(BTreeSet::from([node_id])), true).await?;
+    }
+    app.raft.change_membership(ChangeMembers::RemoveNodes(BTreeSet::from([node_id])), false).await
+}
+
+async fn leave_cluster(app: &App) -> Result<typ::ClientWriteResponse, typ::RaftError<typ::ClientWriteError>> {
+    let metrics = app.raft.metrics().borrow().clone();
+    let leader = if metrics.current_leader == Some(app.id) {
+        match successor(&metrics) {
+            Some(successor) => step_down(app, successor).await.map_err(typ::RaftError::Fatal)?,
+            // The last voter: let the membership change report it.
+            None => return remove(app, app.id).await,
+        }
+    } else {
+        forward_to_leader(&metrics)
+    };
+    let forward = || typ::RaftError::APIError(typ::ClientWriteError::ForwardToLeader(leader.clone()));
+    let leader_addr = match (leader.leader_id, &leader.leader_node) {
+        (Some(leader_id), Some(leader_node)) if leader_id != app.id => leader_node.addr.clone(),
+        _ => return Err(forward()),
+    };
+
+    let resp = reqwest::Client::new()
+        .post(format!("http://{}/remove-node", leader_addr))
+        .timeout(LEAVE_FORWARD_TIMEOUT)
+        .json(&app.id)
+        .send()
+        .await
+        .map_err(|_| forward())?;
+    resp.json::<Result<typ::ClientWriteResponse, typ::RaftError<typ::ClientWriteError>>>().await.map_err(|_| forward())?
+}
+
+/// The voter best placed to take over from this leader: the other voter whose log matches the
+/// leader's the furthest.
+fn successor(metrics: &RaftMetrics<TypeConfig>) -> Option<NodeId> {
+    let matched = |id: &NodeId| {
+        let replication = metrics.replication.as_ref()?;
+        replication.get(id).copied().flatten().map(|log_id| log_id.index)
+    };
+    metrics.membership_config.membership().voter_ids().filter(|id| Some(*id) != metrics.current_leader).max_by_key(matched)
+}
+
+/// Hands leadership of this node over to voter `to` and waits, up to [`LEADER_TRANSFER_TIMEOUT`],
+/// for another node to be leader. Returns the leader to send requests to from now on, unknown if
+/// none was elected in time.
+async fn step_down(app: &App, to: NodeId) -> Result<typ::ForwardToLeader, typ::Fatal> {
+    app.raft.trigger().transfer_leader(to).await?;
+    let id = app.id;
+    let metrics = match app.raft.wait(Some(LEADER_TRANSFER_TIMEOUT))
+        .metrics(move |metrics| metrics.current_leader.is_some_and(|leader| leader != id), "leadership transferred")
+        .await
+    {
+        Ok(metrics) => metrics,
+        Err(_) => app.raft.metrics().borrow().clone(),
+    };
+    Ok(forward_to_leader(&metrics))
+}
+
 /// Initialize a single-node cluster if the `req` is empty vec.
 /// Otherwise initialize a cluster with the `req` specified vec of node-id and node-address
 #[post("/init")]
@@ -60,3 +199,25 @@ pub async fn metrics(app: Data<App>) -> actix_web::Result<impl Responder> {
     Logger::info(format!("metrics response: {:?}", res), true);
     Ok(Json(res))
 }
+
+/// Build a snapshot of this node's state machine now, instead of waiting for the snapshot
+/// policy. Openraft then purges the log it covers, keeping `max_in_snapshot_log_to_keep` entries.
+#[post("/snapshot")]
+pub async fn snapshot(app: Data<App>) -> actix_web::Result<impl Responder> {
+    Logger::info("snapshot request", true);
+    let res: Result<(), typ::RaftError> = app.raft.trigger().snapshot().await.map_err(typ::RaftError::Fatal);
+    Logger::info(format!("snapshot response: {:?}", res), true);
+    Ok(Json(res))
+}
+
+/// Purge this node's log up to and including the given index.
+///
+/// Only entries already covered by a snapshot are purged, and `max_in_snapshot_log_to_keep` is
+/// not taken into account.
+#[post("/purge-log")]
+pub async fn purge_log(app: Data<App>, req: Json<u64>) -> actix_web::Result<impl Responder> {
+    Logger::info(format!("purge-log request: {:?}", req.0), true);
+    let res: Result<(), typ::RaftError> = app.raft.trigger().purge_log(req.0).await.map_err(typ::RaftError::Fatal);
+    Logger::info(format!("purge-log response: {:?}", res), true);
+    Ok(Json(res))
+}
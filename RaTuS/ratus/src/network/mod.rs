@@ -2,6 +2,7 @@ pub mod api;
 pub mod management;
 pub mod raft;
 mod raft_network_impl;
+pub mod snapshot_chunk;
 
 pub use raft_network_impl::Network;
 pub use raft_network_impl::NetworkConnection;
@@ -1,8 +1,9 @@
-use actix_web::{post, web::{Data, Json}, Responder};
-use openraft::raft::{AppendEntriesRequest, InstallSnapshotRequest, VoteRequest};
+use actix_web::{error, post, web::{Bytes, Data, Json}, Responder};
+use openraft::raft::{AppendEntriesRequest, AppendEntriesResponse, VoteRequest};
 
 use system::Logger;
 
+use super::snapshot_chunk;
 use crate::{app::App, TypeConfig};
 
 // --- Raft communication
@@ -17,15 +18,19 @@ pub async fn vote(app: Data<App>, req: Json<VoteRequest<TypeConfig>>) -> actix_w
 
 #[post("/raft-append")]
 pub async fn append(app: Data<App>, req: Json<AppendEntriesRequest<TypeConfig>>) -> actix_web::Result<impl Responder> {
+    let leader_commit = req.0.leader_commit.map(|log_id| log_id.index);
     let res = app.raft.append_entries(req.0).await;
+    // Accepted from the current leader: bounded-staleness reads measure from here.
+    if matches!(res, Ok(AppendEntriesResponse::Success | AppendEntriesResponse::PartialSuccess(_))) {
+        app.record_leader_contact(leader_commit);
+    }
     Ok(Json(res))
 }
 
+/// One chunk of a snapshot, in the binary form of [`snapshot_chunk`].
 #[post("/raft-snapshot")]
-pub async fn snapshot(
-    app: Data<App>,
-    req: Json<InstallSnapshotRequest<TypeConfig>>,
-) -> actix_web::Result<impl Responder> {
-    let res = app.raft.install_snapshot(req.0).await;
+pub async fn snapshot(app: Data<App>, body: Bytes) -> actix_web::Result<impl Responder> {
+    let req = snapshot_chunk::decode(&body).map_err(error::ErrorBadRequest)?;
+    let res = app.raft.install_snapshot(req).await;
     Ok(Json(res))
 }
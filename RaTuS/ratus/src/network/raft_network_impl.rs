@@ -4,8 +4,10 @@ use openraft::{
     raft::{AppendEntriesRequest, AppendEntriesResponse, InstallSnapshotRequest, InstallSnapshotResponse, VoteRequest, VoteResponse}, 
     BasicNode
 };
+use reqwest::header::CONTENT_TYPE;
 use serde::{de::DeserializeOwned, Serialize};
 
+use super::snapshot_chunk;
 use crate::{typ, NodeId, TypeConfig};
 
 pub struct Network {}
@@ -29,7 +31,35 @@ impl Network {
 
         let client = reqwest::Client::new();
 
-        let resp = client.post(url).json(&req).send().await.map_err(|e| {
+        self.send(target, client.post(url).json(&req)).await
+    }
+
+    /// Send one chunk of a snapshot as a binary body, see [`snapshot_chunk`].
+    pub async fn send_snapshot_chunk(
+        &self,
+        target: NodeId,
+        target_node: &BasicNode,
+        req: InstallSnapshotRequest<TypeConfig>,
+    ) -> Result<InstallSnapshotResponse<TypeConfig>, typ::RPCError<InstallSnapshotError>> {
+        let body = snapshot_chunk::encode(req).map_err(|e| openraft::error::RPCError::Network(NetworkError::new(&e)))?;
+
+        let url = format!("http://{}/raft-snapshot", target_node.addr);
+
+        let client = reqwest::Client::new();
+
+        self.send(target, client.post(url).header(CONTENT_TYPE, "application/octet-stream").body(body)).await
+    }
+
+    async fn send<Resp, Err>(
+        &self,
+        target: NodeId,
+        request: reqwest::RequestBuilder,
+    ) -> Result<Resp, openraft::error::RPCError<TypeConfig, Err>>
+    where
+        Err: std::error::Error + DeserializeOwned,
+        Resp: DeserializeOwned,
+    {
+        let resp = request.send().await.map_err(|e| {
             // If the error is a connection error, we return `Unreachable` so that connection isn't retried
             // immediately.
             if e.is_connect() {
@@ -78,7 +108,7 @@ impl RaftNetwork<TypeConfig> for NetworkConnection {
         req: InstallSnapshotRequest<TypeConfig>,
         _option: RPCOption,
     ) -> Result<InstallSnapshotResponse<TypeConfig>, typ::RPCError<InstallSnapshotError>> {
-        self.owner.send_rpc(self.target, &self.target_node, "raft-snapshot", req).await
+        self.owner.send_snapshot_chunk(self.target, &self.target_node, req).await
     }
 
     async fn vote(
@@ -0,0 +1,48 @@
+//! The body of a `raft-snapshot` request, one chunk of a snapshot sent as
+//! `application/octet-stream`: `[header length: u32 LE][header: JSON][chunk data]`. Keeping the
+//! data out of the JSON avoids encoding every byte as a number.
+
+use std::io::{self, ErrorKind};
+
+use openraft::{raft::InstallSnapshotRequest, SnapshotMeta, Vote};
+use serde::{Deserialize, Serialize};
+
+use crate::{NodeId, TypeConfig};
+
+/// Room a request needs besides its chunk data. Generous: the header is mostly the membership.
+pub const MAX_HEADER_LEN: usize = 1024 * 1024;
+
+#[derive(Serialize, Deserialize)]
+struct ChunkHeader {
+    vote: Vote<NodeId>,
+    meta: SnapshotMeta<TypeConfig>,
+    offset: u64,
+    done: bool,
+}
+
+pub fn encode(req: InstallSnapshotRequest<TypeConfig>) -> io::Result<Vec<u8>> {
+    let InstallSnapshotRequest { vote, meta, offset, data, done } = req;
+    let header = serde_json::to_vec(&ChunkHeader { vote, meta, offset, done }).map_err(io::Error::from)?;
+    let header_len = u32::try_from(header.len()).map_err(|_| io::Error::new(ErrorKind::InvalidInput, "snapshot chunk header too large"))?;
+
+    let mut body = Vec::with_capacity(4 + header.len() + data.len());
+    body.extend_from_slice(&header_len.to_le_bytes());
+    body.extend_from_slice(&header);
+    body.extend_from_slice(&data);
+    Ok(body)
+}
+
+pub fn decode(body: &[u8]) -> io::Result<InstallSnapshotRequest<TypeConfig>> {
+    let truncated = || io::Error::new(ErrorKind::InvalidData, "truncated snapshot chunk");
+    let header_len = u32::from_le_bytes(body.get(..4).ok_or_else(truncated)?.try_into().map_err(|_| truncated())?) as usize;
+    let header = body.get(4..4 + header_len).ok_or_else(truncated)?;
+    let ChunkHeader { vote, meta, offset, done } = serde_json::from_slice(header).map_err(io::Error::from)?;
+
+    Ok(InstallSnapshotRequest {
+        vote,
+        meta,
+        offset,
+        data: body[4 + header_len..].to_vec(),
+        done,
+    })
+}
@@ -0,0 +1,496 @@
+//! Provide `FileLogStore`, a file-backed implementation of `RaftLogStorage` that survives
+//! restarts.
+//!
+//! Layout of the data directory:
+//!
+//! - `log/<first index>.log`: segment files. Each holds consecutive entries as records of
+//!   `[payload length: u32 LE][crc32 of payload: u32 LE][payload: JSON entry]`. A new segment is
+//!   started once the active one exceeds `SEGMENT_SIZE`.
+//! - `vote.json`, `committed.json`, `purged.json`: the last saved vote, committed and purged log
+//!   ids, each replaced atomically with a write-fsync-rename.
+//!
+//! Appends are fsynced before `LogFlushed` is signalled. A torn record at the end of the last
+//! segment, left by a crash mid-append, was never reported as flushed and is cut off on open; a
+//! bad record anywhere else is reported as corruption.
+//!
+//! All entries are also kept in memory to serve reads, like `LogStore`.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Debug;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, ErrorKind, Write};
+use std::ops::RangeBounds;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use openraft::storage::LogFlushed;
+use openraft::LogId;
+use openraft::LogState;
+use openraft::RaftLogId;
+use openraft::RaftTypeConfig;
+use openraft::StorageError;
+use openraft::StorageIOError;
+use openraft::Vote;
+use serde::{de::DeserializeOwned, Serialize};
+use tokio::sync::Mutex;
+
+use system::Logger;
+
+/// Size in bytes past which appends go to a new segment file.
+pub const SEGMENT_SIZE: u64 = 8 * 1024 * 1024;
+
+const LOG_DIR: &str = "log";
+const SEGMENT_EXTENSION: &str = "log";
+const VOTE_FILE: &str = "vote.json";
+const COMMITTED_FILE: &str = "committed.json";
+const PURGED_FILE: &str = "purged.json";
+
+/// Bytes in front of every record payload: its length and its crc32.
+const RECORD_HEADER_SIZE: usize = 8;
+
+/// RaftLogStore implementation persisting to a data directory
+#[derive(Clone, Debug)]
+pub struct FileLogStore<C: RaftTypeConfig> {
+    inner: Arc<Mutex<FileLogStoreInner<C>>>,
+}
+
+impl<C: RaftTypeConfig> FileLogStore<C>
+where C::Entry: Serialize + DeserializeOwned
+{
+    /// Opens the log kept in `data_dir`, creating the directory if needed, and loads every
+    /// entry, vote and log id saved there.
+    pub fn open(data_dir: impl AsRef<Path>) -> io::Result<Self> {
+        let inner = FileLogStoreInner::open(data_dir.as_ref())?;
+        Ok(Self { inner: Arc::new(Mutex::new(inner)) })
+    }
+}
+
+#[derive(Debug)]
+pub struct FileLogStoreInner<C: RaftTypeConfig> {
+    /// The data directory, holding the meta files.
+    data_dir: PathBuf,
+
+    /// The directory holding the segment files.
+    log_dir: PathBuf,
+
+    /// The last purged log id.
+    last_purged_log_id: Option<LogId<C::NodeId>>,
+
+    /// The Raft log.
+    log: BTreeMap<u64, C::Entry>,
+
+    /// Where the record of each entry starts: its segment and byte offset in it.
+    locations: BTreeMap<u64, (u64, u64)>,
+
+    /// Length in bytes of each segment, by the index of its first entry.
+    segments: BTreeMap<u64, u64>,
+
+    /// The commit log id.
+    committed: Option<LogId<C::NodeId>>,
+
+    /// The current granted vote.
+    vote: Option<Vote<C::NodeId>>,
+}
+
+impl<C: RaftTypeConfig> FileLogStoreInner<C>
+where C::Entry: Serialize + DeserializeOwned
+{
+    fn open(data_dir: &Path) -> io::Result<Self> {
+        let log_dir = data_dir.join(LOG_DIR);
+        fs::create_dir_all(&log_dir)?;
+
+        let mut inner = Self {
+            data_dir: data_dir.to_path_buf(),
+            log_dir,
+            last_purged_log_id: read_meta(&data_dir.join(PURGED_FILE))?,
+            log: BTreeMap::new(),
+            locations: BTreeMap::new(),
+            segments: BTreeMap::new(),
+            committed: read_meta(&data_dir.join(COMMITTED_FILE))?,
+            vote: read_meta(&data_dir.join(VOTE_FILE))?,
+        };
+
+        let segments = inner.list_segments()?;
+        let last_segment = segments.last().copied();
+        for segment in segments {
+            inner.load_segment(segment, Some(segment) == last_segment)?;
+        }
+        // Segments left holding only purged entries by a crash mid-purge.
+        inner.remove_unreferenced_segments()?;
+        Ok(inner)
+    }
+
+    fn segment_path(&self, segment: u64) -> PathBuf {
+        self.log_dir.join(format!("{:020}.{}", segment, SEGMENT_EXTENSION))
+    }
+
+    fn list_segments(&self) -> io::Result<Vec<u64>> {
+        let mut segments = Vec::new();
+        for dir_entry in fs::read_dir(&self.log_dir)? {
+            let path = dir_entry?.path();
+            if path.extension().and_then(|extension| extension.to_str()) != Some(SEGMENT_EXTENSION) {
+                continue;
+            }
+            if let Some(segment) = path.file_stem().and_then(|stem| stem.to_str()).and_then(|stem| stem.parse().ok()) {
+                segments.push(segment);
+            }
+        }
+        segments.sort_unstable();
+        Ok(segments)
+    }
+
+    /// Reads the records of `segment`. A bad record cuts off the rest of the last segment and is
+    /// an error anywhere else.
+    fn load_segment(&mut self, segment: u64, is_last: bool) -> io::Result<()> {
+        let path = self.segment_path(segment);
+        let bytes = fs::read(&path)?;
+        let purged_index = self.last_purged_log_id.map(|log_id| log_id.index);
+
+        let mut offset = 0;
+        while offset < bytes.len() {
+            if let Some((entry, record_len)) = decode_record::<C::Entry>(&bytes[offset..]) {
+                let index = entry.get_log_id().index;
+                if Some(index) > purged_index {
+                    self.locations.insert(index, (segment, offset as u64));
+                    self.log.insert(index, entry);
+                }
+                offset += record_len;
+                continue;
+            }
+            if !is_last {
+                return Err(io::Error::new(
+                    ErrorKind::InvalidData,
+                    format!("corrupt record at byte {} of {}", offset, path.display()),
+                ));
+            }
+            Logger::warn(format!("Dropping torn tail of {} from byte {}", path.display(), offset), true);
+            let file = OpenOptions::new().write(true).open(&path)?;
+            file.set_len(offset as u64)?;
+            file.sync_all()?;
+            break;
+        }
+        self.segments.insert(segment, offset as u64);
+        Ok(())
+    }
+
+    /// Deletes the segment files no longer holding any entry.
+    fn remove_unreferenced_segments(&mut self) -> io::Result<()> {
+        let referenced: BTreeSet<u64> = self.locations.values().map(|(segment, _)| *segment).collect();
+        let unreferenced: Vec<u64> = self.segments.keys().filter(|segment| !referenced.contains(segment)).copied().collect();
+        for segment in unreferenced.iter() {
+            fs::remove_file(self.segment_path(*segment))?;
+            self.segments.remove(segment);
+        }
+        if !unreferenced.is_empty() {
+            sync_dir(&self.log_dir)?;
+        }
+        Ok(())
+    }
+
+    /// The segment to append the entry at `index` to, started anew if the active one is full.
+    fn segment_for_append(&mut self, index: u64) -> u64 {
+        match self.segments.iter().next_back() {
+            Some((segment, len)) if *len < SEGMENT_SIZE => *segment,
+            _ => {
+                self.segments.insert(index, 0);
+                index
+            }
+        }
+    }
+
+    async fn try_get_log_entries<RB: RangeBounds<u64> + Clone + Debug>(
+        &mut self,
+        range: RB,
+    ) -> Result<Vec<C::Entry>, StorageError<C::NodeId>>
+    where
+        C::Entry: Clone,
+    {
+        let response = self.log.range(range.clone()).map(|(_, val)| val.clone()).collect::<Vec<_>>();
+        Ok(response)
+    }
+
+    async fn get_log_state(&mut self) -> Result<LogState<C>, StorageError<C::NodeId>> {
+        let last = self.log.iter().next_back().map(|(_, ent)| *ent.get_log_id());
+
+        let last_purged = self.last_purged_log_id;
+
+        let last = match last {
+            None => last_purged,
+            Some(x) => Some(x),
+        };
+
+        Ok(LogState {
+            last_purged_log_id: last_purged,
+            last_log_id: last,
+        })
+    }
+
+    async fn save_committed(&mut self, committed: Option<LogId<C::NodeId>>) -> Result<(), StorageError<C::NodeId>> {
+        write_meta(&self.data_dir, COMMITTED_FILE, &committed).map_err(|e| StorageIOError::write(&e))?;
+        self.committed = committed;
+        Ok(())
+    }
+
+    async fn read_committed(&mut self) -> Result<Option<LogId<C::NodeId>>, StorageError<C::NodeId>> {
+        Ok(self.committed)
+    }
+
+    async fn save_vote(&mut self, vote: &Vote<C::NodeId>) -> Result<(), StorageError<C::NodeId>> {
+        write_meta(&self.data_dir, VOTE_FILE, &Some(*vote)).map_err(|e| StorageIOError::write_vote(&e))?;
+        self.vote = Some(*vote);
+        Ok(())
+    }
+
+    async fn read_vote(&mut self) -> Result<Option<Vote<C::NodeId>>, StorageError<C::NodeId>> {
+        Ok(self.vote)
+    }
+
+    async fn append<I>(&mut self, entries: I, callback: LogFlushed<C>) -> Result<(), StorageError<C::NodeId>>
+    where I: IntoIterator<Item = C::Entry> {
+        match self.write_entries(entries) {
+            Ok(()) => {
+                callback.log_io_completed(Ok(()));
+                Ok(())
+            }
+            Err(e) => {
+                callback.log_io_completed(Err(io::Error::new(e.kind(), e.to_string())));
+                Err(StorageIOError::write_logs(&e).into())
+            }
+        }
+    }
+
+    /// Writes `entries` to the segment files and fsyncs them.
+    fn write_entries<I>(&mut self, entries: I) -> io::Result<()>
+    where I: IntoIterator<Item = C::Entry> {
+        let mut writer: Option<(u64, File)> = None;
+        let mut created_segment = false;
+
+        for entry in entries {
+            let index = entry.get_log_id().index;
+            let record = encode_record(&entry)?;
+
+            let segment = self.segment_for_append(index);
+            if writer.as_ref().map(|(current, _)| *current) != Some(segment) {
+                if let Some((_, file)) = writer.take() {
+                    file.sync_data()?;
+                }
+                let path = self.segment_path(segment);
+                created_segment |= !path.exists();
+                writer = Some((segment, OpenOptions::new().create(true).append(true).open(path)?));
+            }
+            if let Some((_, file)) = writer.as_mut() {
+                file.write_all(&record)?;
+            }
+
+            let len = self.segments.entry(segment).or_default();
+            self.locations.insert(index, (segment, *len));
+            *len += record.len() as u64;
+            self.log.insert(index, entry);
+        }
+
+        if let Some((_, file)) = writer {
+            file.sync_data()?;
+        }
+        if created_segment {
+            sync_dir(&self.log_dir)?;
+        }
+        Ok(())
+    }
+
+    async fn truncate(&mut self, log_id: LogId<C::NodeId>) -> Result<(), StorageError<C::NodeId>> {
+        self.truncate_files(log_id.index).map_err(|e| StorageIOError::write_logs(&e))?;
+
+        let keys = self.log.range(log_id.index..).map(|(k, _v)| *k).collect::<Vec<_>>();
+        for key in keys {
+            self.log.remove(&key);
+        }
+
+        Ok(())
+    }
+
+    /// Cuts the segment files back to the entries before `index`, latest segments first.
+    fn truncate_files(&mut self, index: u64) -> io::Result<()> {
+        let (segment, offset) = match self.locations.get(&index) {
+            Some(location) => *location,
+            None => return Ok(()),
+        };
+
+        let later_segments: Vec<u64> = self.segments.range(segment + 1..).map(|(segment, _)| *segment).collect();
+        for later_segment in later_segments.into_iter().rev() {
+            fs::remove_file(self.segment_path(later_segment))?;
+            self.segments.remove(&later_segment);
+        }
+
+        if offset == 0 {
+            fs::remove_file(self.segment_path(segment))?;
+            self.segments.remove(&segment);
+        } else {
+            let file = OpenOptions::new().write(true).open(self.segment_path(segment))?;
+            file.set_len(offset)?;
+            file.sync_all()?;
+            self.segments.insert(segment, offset);
+        }
+        sync_dir(&self.log_dir)?;
+
+        self.locations.retain(|entry_index, _| *entry_index < index);
+        Ok(())
+    }
+
+    async fn purge(&mut self, log_id: LogId<C::NodeId>) -> Result<(), StorageError<C::NodeId>> {
+        {
+            let ld = &mut self.last_purged_log_id;
+            assert!(*ld <= Some(log_id));
+            // Persisted first: entries up to it are skipped on open even if their segments
+            // outlive a crash.
+            write_meta(&self.data_dir, PURGED_FILE, &Some(log_id)).map_err(|e| StorageIOError::write_logs(&e))?;
+            *ld = Some(log_id);
+        }
+
+        {
+            let keys = self.log.range(..=log_id.index).map(|(k, _v)| *k).collect::<Vec<_>>();
+            for key in keys {
+                self.log.remove(&key);
+                self.locations.remove(&key);
+            }
+        }
+
+        self.remove_unreferenced_segments().map_err(|e| StorageIOError::write_logs(&e))?;
+
+        Ok(())
+    }
+}
+
+fn encode_record<T: Serialize>(value: &T) -> io::Result<Vec<u8>> {
+    let payload = serde_json::to_vec(value).map_err(io::Error::from)?;
+    let len = u32::try_from(payload.len()).map_err(|_| io::Error::new(ErrorKind::InvalidInput, "log entry too large"))?;
+
+    let mut record = Vec::with_capacity(RECORD_HEADER_SIZE + payload.len());
+    record.extend_from_slice(&len.to_le_bytes());
+    record.extend_from_slice(&crc32fast::hash(&payload).to_le_bytes());
+    record.extend_from_slice(&payload);
+    Ok(record)
+}
+
+/// The value in the record at the start of `bytes` and the record's length, or `None` if the
+/// record is incomplete, fails its checksum or does not decode.
+fn decode_record<T: DeserializeOwned>(bytes: &[u8]) -> Option<(T, usize)> {
+    let header = bytes.get(..RECORD_HEADER_SIZE)?;
+    let len = u32::from_le_bytes(header[..4].try_into().ok()?) as usize;
+    let crc = u32::from_le_bytes(header[4..].try_into().ok()?);
+
+    let payload = bytes.get(RECORD_HEADER_SIZE..RECORD_HEADER_SIZE + len)?;
+    if crc32fast::hash(payload) != crc {
+        return None;
+    }
+    let value = serde_json::from_slice(payload).ok()?;
+    Some((value, RECORD_HEADER_SIZE + len))
+}
+
+/// Reads a meta file, `None` if it was never written.
+fn read_meta<T: DeserializeOwned>(path: &Path) -> io::Result<Option<T>> {
+    match fs::read(path) {
+        Ok(bytes) => serde_json::from_slice(&bytes).map_err(io::Error::from),
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+/// Replaces a meta file atomically: the new content is fsynced under a temporary name and then
+/// renamed over the old file.
+fn write_meta<T: Serialize>(dir: &Path, name: &str, value: &T) -> io::Result<()> {
+    let temporary_path = dir.join(format!("{}.tmp", name));
+    let mut file = File::create(&temporary_path)?;
+    file.write_all(&serde_json::to_vec(value).map_err(io::Error::from)?)?;
+    file.sync_all()?;
+    fs::rename(&temporary_path, dir.join(name))?;
+    sync_dir(dir)
+}
+
+/// Makes file creations, renames and removals in `dir` durable.
+pub(super) fn sync_dir(dir: &Path) -> io::Result<()> {
+    // Directories cannot be opened for syncing on Windows; renames there are durable already.
+    if cfg!(windows) {
+        return Ok(());
+    }
+    File::open(dir)?.sync_all()
+}
+
+mod impl_log_store {
+    use std::fmt::Debug;
+    use std::ops::RangeBounds;
+
+    use openraft::storage::LogFlushed;
+    use openraft::storage::RaftLogStorage;
+    use openraft::LogId;
+    use openraft::LogState;
+    use openraft::RaftLogReader;
+    use openraft::RaftTypeConfig;
+    use openraft::StorageError;
+    use openraft::Vote;
+    use serde::{de::DeserializeOwned, Serialize};
+
+    use crate::store::file_log_store::FileLogStore;
+
+    impl<C: RaftTypeConfig> RaftLogReader<C> for FileLogStore<C>
+    where C::Entry: Clone + Serialize + DeserializeOwned
+    {
+        async fn try_get_log_entries<RB: RangeBounds<u64> + Clone + Debug>(
+            &mut self,
+            range: RB,
+        ) -> Result<Vec<C::Entry>, StorageError<C::NodeId>> {
+            let mut inner = self.inner.lock().await;
+            inner.try_get_log_entries(range).await
+        }
+
+        async fn read_vote(&mut self) -> Result<Option<Vote<C::NodeId>>, StorageError<C::NodeId>> {
+            let mut inner = self.inner.lock().await;
+            inner.read_vote().await
+        }
+    }
+
+    impl<C: RaftTypeConfig> RaftLogStorage<C> for FileLogStore<C>
+    where C::Entry: Clone + Serialize + DeserializeOwned
+    {
+        type LogReader = Self;
+
+        async fn get_log_state(&mut self) -> Result<LogState<C>, StorageError<C::NodeId>> {
+            let mut inner = self.inner.lock().await;
+            inner.get_log_state().await
+        }
+
+        async fn save_committed(&mut self, committed: Option<LogId<C::NodeId>>) -> Result<(), StorageError<C::NodeId>> {
+            let mut inner = self.inner.lock().await;
+            inner.save_committed(committed).await
+        }
+
+        async fn read_committed(&mut self) -> Result<Option<LogId<C::NodeId>>, StorageError<C::NodeId>> {
+            let mut inner = self.inner.lock().await;
+            inner.read_committed().await
+        }
+
+        async fn save_vote(&mut self, vote: &Vote<C::NodeId>) -> Result<(), StorageError<C::NodeId>> {
+            let mut inner = self.inner.lock().await;
+            inner.save_vote(vote).await
+        }
+
+        async fn append<I>(&mut self, entries: I, callback: LogFlushed<C>) -> Result<(), StorageError<C::NodeId>>
+        where I: IntoIterator<Item = C::Entry> {
+            let mut inner = self.inner.lock().await;
+            inner.append(entries, callback).await
+        }
+
+        async fn truncate(&mut self, log_id: LogId<C::NodeId>) -> Result<(), StorageError<C::NodeId>> {
+            let mut inner = self.inner.lock().await;
+            inner.truncate(log_id).await
+        }
+
+        async fn purge(&mut self, log_id: LogId<C::NodeId>) -> Result<(), StorageError<C::NodeId>> {
+            let mut inner = self.inner.lock().await;
+            inner.purge(log_id).await
+        }
+
+        async fn get_log_reader(&mut self) -> Self::LogReader {
+            self.clone()
+        }
+    }
+}
@@ -1,4 +1,4 @@
-use std::{fmt::Debug, io::Cursor, sync::{atomic::{AtomicU64, Ordering}, Arc}};
+use std::{fmt::Debug, io::{self, BufReader, Read, Seek}, path::{Path, PathBuf}, sync::{atomic::{AtomicU64, Ordering}, Arc}};
 
 use openraft::{
     raft::VoteRequest,
@@ -7,35 +7,132 @@ use openraft::{
     Entry, EntryPayload, LogId, RaftSnapshotBuilder, SnapshotMeta, StorageError, StorageIOError, StoredMembership
 };
 use serde::{Deserialize, Serialize};
-use tokio::sync::RwLock;
+use tokio::sync::{Notify, RwLock};
 
 use ts_core::{
     store::Store,
-    vec_store::VecStore,
+    indexed_store::IndexedStore,
     mutex_store::MutexStore,
+    policy::MatchPolicy,
     tuple::Tuple,
     query_tuple::QueryTuple,
 };
 
 use crate::{NodeId, TypeConfig};
 
+pub mod file_log_store;
 pub mod log_store;
-
-pub type TupleStore = MutexStore<VecStore>;
+pub mod node_log_store;
+pub mod snapshot_buffer;
+mod snapshot_file;
+mod snapshot_format;
+pub mod session;
+pub mod transaction;
+pub mod watch;
+
+pub use session::Sessions;
+pub use snapshot_buffer::SnapshotBuffer;
+pub use transaction::Op;
+pub use watch::WatchHub;
+
+/// The tuple store replicated by the state machine. Swap the inner store here to change how
+/// tuples are held, e.g. `MutexStore<VecStore>` for a plain linear scan.
+pub type TupleStore = MutexStore<IndexedStore>;
 
 pub type LogStore = log_store::LogStore<TypeConfig>;
 
+pub type FileLogStore = file_log_store::FileLogStore<TypeConfig>;
+
+pub use node_log_store::NodeLogStore;
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub enum Request {
     Set { tuple: Tuple},
     Get { query: QueryTuple },
+    /// Removes every tuple matching `query` (at most `limit`) in a single log entry.
+    GetAll { query: QueryTuple, limit: Option<usize> },
+    /// Writes a tuple that is dropped by the first `Tick` at least `ttl_ms` past the state
+    /// machine clock at the time this entry is applied.
+    SetWithTtl { tuple: Tuple, ttl_ms: u64 },
+    /// Takes one tuple matching `query` and writes `tuple` in its place within the same log
+    /// entry. Nothing is written if there is no match; the response holds the taken tuple.
+    Replace { query: QueryTuple, tuple: Tuple },
+    /// Advances the state machine clock to `now`, in milliseconds since the Unix epoch, and
+    /// drops the tuples expired by then. Proposed by the leader when a tuple is due, and before a
+    /// `SetWithTtl` if the clock lags, so every replica expires the same tuples at the same log
+    /// index.
+    Tick { now: u64 },
+    /// Applies the ops in order within a single log entry, all or nothing: if any `Op::Take`
+    /// finds no match, none of the ops take effect.
+    Transaction(Vec<Op>),
+    /// `request` as the `seq`th request of client `client_id`. Applied once however often it is
+    /// retried: a retry gets the response of the first attempt, see [`Sessions`]. A client numbers
+    /// its requests from 1 and waits for each before sending the next.
+    Session { client_id: u64, seq: u64, request: Box<Request> },
+}
+
+impl Request {
+    /// Whether applying it reads the state machine clock, which only moves with `Tick`s.
+    pub fn reads_clock(&self) -> bool {
+        match self {
+            Request::SetWithTtl { .. } => true,
+            Request::Session { request, .. } => request.reads_clock(),
+            _ => false,
+        }
+    }
+
+    /// Whether it is, or wraps, a `Tick`. Only the leader's ticker proposes those; a transaction
+    /// has no op for one.
+    pub fn is_tick(&self) -> bool {
+        match self {
+            Request::Tick { .. } => true,
+            Request::Session { request, .. } => request.is_tick(),
+            _ => false,
+        }
+    }
 }
 
 pub type RaftVoteRequest = VoteRequest<TypeConfig>;
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
 pub struct Response {
     pub value: Option<Tuple>,
+
+    /// Tuples taken by a `GetAll`, empty for every other request.
+    #[serde(default)]
+    pub values: Vec<Tuple>,
+
+    /// One result per op of a committed `Transaction`: the tuple taken or read, `None` for a
+    /// write. Empty for every other request and for an aborted transaction.
+    #[serde(default)]
+    pub results: Vec<Option<Tuple>>,
+
+    /// Index of the op that aborted a `Transaction`, which then had no effect.
+    #[serde(default)]
+    pub aborted_at: Option<usize>,
+
+    /// The `Session` request was applied before and this is a retry. The rest of the response is
+    /// the first attempt's, or empty if that is no longer kept.
+    #[serde(default)]
+    pub duplicate: bool,
+}
+
+impl Response {
+    pub fn value(value: Option<Tuple>) -> Self {
+        Self { value, ..Default::default() }
+    }
+
+    pub fn values(values: Vec<Tuple>) -> Self {
+        Self { values, ..Default::default() }
+    }
+
+    pub fn committed(results: Vec<Option<Tuple>>) -> Self {
+        Self { results, ..Default::default() }
+    }
+
+    pub fn aborted(op_index: usize) -> Self {
+        Self { aborted_at: Some(op_index), ..Default::default() }
+    }
 }
 
 #[derive(Debug)]
@@ -50,8 +147,65 @@ pub struct StateMachineData {
 
     pub last_membership: StoredMembership<TypeConfig>,
 
-    /// Application data.
-    pub data: TupleStore
+    /// The latest `Tick` applied. Never read from the local clock, so replicas agree on it.
+    #[serde(default)]
+    pub clock: u64,
+
+    /// Application data. Its match policy, including the generator state of
+    /// [`MatchPolicy::Random`], is part of the data: only applied log entries advance it and
+    /// snapshots carry it, so every replica takes the same tuple for the same `Get`.
+    pub data: TupleStore,
+
+    /// The last request of every recent client, to answer retries.
+    #[serde(default)]
+    pub sessions: Sessions,
+}
+
+impl StateMachineData {
+    fn apply_request(&mut self, req: &Request, log_index: u64) -> Result<Response, StorageError<NodeId>> {
+        let response = match req {
+            Request::Get { query } => {
+                let value = self.data.get(query);
+                match value {
+                    Ok(v) => Response::value(v),
+                    Err(_) => Response::value(None),
+                }
+            },
+            Request::Set { tuple } => {
+                let _ = self.data.write(tuple);
+                Response::value(Some(tuple.clone()))
+            },
+            Request::GetAll { query, limit } => {
+                let values = self.data.get_all(query, *limit).unwrap_or_default();
+                Response::values(values)
+            },
+            Request::SetWithTtl { tuple, ttl_ms } => {
+                let expires_at = self.clock.saturating_add(*ttl_ms);
+                let _ = self.data.write_with_expiry(tuple, expires_at);
+                Response::value(Some(tuple.clone()))
+            },
+            Request::Replace { query, tuple } => {
+                let replaced = self.data.replace(query, tuple).unwrap_or_default();
+                Response::value(replaced)
+            },
+            Request::Transaction(ops) => transaction::apply(&mut self.data, ops)?,
+            Request::Tick { now } => {
+                // A new leader's clock may lag the old one's; never move backwards.
+                self.clock = self.clock.max(*now);
+                let _ = self.data.expire(self.clock);
+                Response::value(None)
+            },
+            Request::Session { client_id, seq, request } => {
+                if let Some(response) = self.sessions.duplicate(*client_id, *seq) {
+                    return Ok(response);
+                }
+                let response = self.apply_request(request, log_index)?;
+                self.sessions.record(*client_id, *seq, &response, log_index);
+                response
+            },
+        };
+        Ok(response)
+    }
 }
 
 /// Defines a state machine for the Raft cluster. This state machine represents a copy of the
@@ -62,15 +216,135 @@ pub struct StateMachineStore {
     pub state_machine: RwLock<StateMachineData>,
     snapshot_idx: AtomicU64,
 
-    /// The last received snapshot.
+    /// The last snapshot built or received, if there is no snapshot directory to read it from.
     current_snapshot: RwLock<Option<StoredSnapshot>>,
+
+    /// Where every new snapshot is saved, if anywhere.
+    snapshot_dir: Option<PathBuf>,
+
+    /// Serialized size of the requests applied since the last snapshot, roughly what the log
+    /// holds beyond it. Only counted with `measure_applied_bytes`.
+    applied_bytes_since_snapshot: AtomicU64,
+
+    /// Whether to count `applied_bytes_since_snapshot`, which serializes every applied request.
+    measure_applied_bytes: bool,
+
+    /// Whether new snapshots are LZ4 compressed. Either kind is read regardless.
+    compress_snapshots: bool,
+
+    /// Signalled after applying requests, which may have written tuples.
+    applied: Notify,
+
+    /// Tuple events of the applied requests, for watchers.
+    watch: WatchHub,
+}
+
+/// The part of the state machine a snapshot carries besides its metadata. Encoded by
+/// [`snapshot_format`]; the serde form is only read, from snapshots taken before that format.
+#[derive(Deserialize, Debug, Default)]
+struct SnapshotState {
+    #[serde(default)]
+    clock: u64,
+    data: TupleStore,
+    #[serde(default)]
+    sessions: Sessions,
+}
+
+impl StateMachineStore {
+    /// A state machine whose store hands out matches by `policy`. All nodes of a cluster must be
+    /// started with the same policy.
+    pub fn with_policy(policy: MatchPolicy) -> Self {
+        StateMachineStore {
+            state_machine: RwLock::new(StateMachineData {
+                data: MutexStore::new(IndexedStore::with_policy(policy)),
+                ..Default::default()
+            }),
+            ..Default::default()
+        }
+    }
+
+    /// A state machine saving its snapshots under `data_dir`, restored from the latest one
+    /// saved there. Openraft then only replays the log entries after that snapshot.
+    pub fn open(data_dir: impl AsRef<Path>) -> io::Result<Self> {
+        let snapshot_dir = snapshot_file::snapshot_dir(data_dir.as_ref())?;
+        let mut state_machine_store = StateMachineStore {
+            snapshot_dir: Some(snapshot_dir.clone()),
+            ..Default::default()
+        };
+
+        if let Some((meta, file)) = snapshot_file::open(&snapshot_dir)? {
+            let snapshot_state = snapshot_format::decode(BufReader::new(file))?;
+            state_machine_store.state_machine = RwLock::new(StateMachineData {
+                last_applied_log: meta.last_log_id,
+                last_membership: meta.last_membership.clone(),
+                clock: snapshot_state.clock,
+                data: snapshot_state.data,
+                sessions: snapshot_state.sessions,
+            });
+            // Keep snapshot ids unique across restarts.
+            let snapshot_idx = meta.snapshot_id.rsplit('-').next().and_then(|idx| idx.parse().ok()).unwrap_or(0);
+            state_machine_store.snapshot_idx = AtomicU64::new(snapshot_idx);
+            if let Some(last_log_id) = meta.last_log_id {
+                state_machine_store.watch.skip_through(last_log_id.index);
+            }
+        }
+        Ok(state_machine_store)
+    }
+
+    pub fn applied_bytes_since_snapshot(&self) -> u64 {
+        self.applied_bytes_since_snapshot.load(Ordering::Relaxed)
+    }
+
+    /// Waits until requests are applied, or returns at once if some were since the last call.
+    /// Meant for a single task, the blocking take dispatcher: concurrent callers share one wakeup.
+    pub async fn applied(&self) {
+        self.applied.notified().await
+    }
+
+    /// The tuple events of the requests this node applies.
+    pub fn watch(&self) -> &WatchHub {
+        &self.watch
+    }
+
+    /// Compress the snapshots this state machine builds from now on.
+    pub fn compress_snapshots(mut self, compress: bool) -> Self {
+        self.compress_snapshots = compress;
+        self
+    }
+
+    /// Count the bytes of the requests applied since the last snapshot, for a snapshot trigger
+    /// on log size. Costs a serialization of every request, so off by default.
+    pub fn measure_applied_bytes(mut self, measure: bool) -> Self {
+        self.measure_applied_bytes = measure;
+        self
+    }
+
+    /// Makes the snapshot of `meta` with `data` the current one: saves it to the snapshot
+    /// directory if there is one, returns it to keep in memory otherwise.
+    fn persist_snapshot(&self, meta: &SnapshotMeta<TypeConfig>, mut data: impl Read) -> Result<Option<StoredSnapshot>, StorageError<NodeId>> {
+        let write_error = |e: io::Error| StorageError::from(StorageIOError::write_snapshot(Some(meta.signature()), &e));
+        match &self.snapshot_dir {
+            Some(dir) => {
+                snapshot_file::save(dir, meta, &mut data).map_err(write_error)?;
+                Ok(None)
+            }
+            None => {
+                let mut bytes = Vec::new();
+                data.read_to_end(&mut bytes).map_err(write_error)?;
+                Ok(Some(StoredSnapshot { meta: meta.clone(), data: bytes }))
+            }
+        }
+    }
 }
 
 impl RaftSnapshotBuilder<TypeConfig> for Arc<StateMachineStore> {
     async fn build_snapshot(&mut self) -> Result<Snapshot<TypeConfig>, StorageError<NodeId>> {
         // Serialize the data of the state machine.
         let state_machine = self.state_machine.read().await;
-        let data = serde_json::to_vec(&state_machine.data).map_err(|e| StorageIOError::read_state_machine(&e))?;
+        let data = snapshot_format::encode(&state_machine, self.compress_snapshots)
+            .map_err(|e| StorageIOError::read_state_machine(&e))?;
+        // What this snapshot covers; requests applied once the lock is released are not.
+        let covered_bytes = self.applied_bytes_since_snapshot.load(Ordering::Relaxed);
 
         let last_applied_log = state_machine.last_applied_log;
         let last_membership = state_machine.last_membership.clone();
@@ -93,16 +367,14 @@ impl RaftSnapshotBuilder<TypeConfig> for Arc<StateMachineStore> {
             snapshot_id,
         };
 
-        let snapshot = StoredSnapshot {
-            meta: meta.clone(),
-            data: data.clone(),
-        };
+        let snapshot = self.persist_snapshot(&meta, data.as_slice())?;
+        let _ = self.applied_bytes_since_snapshot.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |bytes| Some(bytes.saturating_sub(covered_bytes)));
 
-        *current_snapshot = Some(snapshot);
+        *current_snapshot = snapshot;
 
         Ok(Snapshot {
             meta,
-            snapshot: Box::new(Cursor::new(data)),
+            snapshot: Box::new(SnapshotBuffer::memory(data)),
         })
     }
 }
@@ -122,39 +394,43 @@ impl RaftStateMachine<TypeConfig> for Arc<StateMachineStore> {
         let mut res = Vec::new(); //No `with_capacity`; do not know `len` of iterator
 
         let mut sm = self.state_machine.write().await;
+        let mut applied_requests = false;
 
         for entry in entries {
             sm.last_applied_log = Some(entry.log_id);
 
             match entry.payload {
-                EntryPayload::Blank => res.push(Response { value: None }),
-                EntryPayload::Normal(ref req) => match req {
-                    Request::Get { query } => {
-                        let value = sm.data.get(query);
-                        let response = match value {
-                            Ok(v) => Response { value: v },
-                            Err(_) => Response { value: None },
-                        };
-                        res.push(response);
-                    },
-                    Request::Set { tuple } => {
-                        let _ = sm.data.write(tuple);
-                        res.push(Response {
-                            value: Some(tuple.clone()),
-                        })
-                    },
+                EntryPayload::Blank => res.push(Response::value(None)),
+                EntryPayload::Normal(ref req) => {
+                    if self.measure_applied_bytes {
+                        let size = serde_json::to_vec(req).map(|bytes| bytes.len() as u64).unwrap_or(0);
+                        self.applied_bytes_since_snapshot.fetch_add(size, Ordering::Relaxed);
+                    }
+                    let response = sm.apply_request(req, entry.log_id.index)?;
+                    self.watch.publish(entry.log_id.index, req, &response);
+                    res.push(response);
+                    applied_requests = true;
                 },
                 EntryPayload::Membership(ref mem) => {
                     sm.last_membership = StoredMembership::new(Some(entry.log_id), mem.clone());
-                    res.push(Response { value: None })
+                    res.push(Response::value(None))
                 }
             };
         }
+        if applied_requests {
+            self.applied.notify_one();
+        }
         Ok(res)
     }
 
     async fn begin_receiving_snapshot(&mut self) -> Result<Box<SnapshotDataOf<TypeConfig>>, StorageError<NodeId>> {
-        Ok(Box::new(Cursor::new(Vec::new())))
+        match &self.snapshot_dir {
+            Some(dir) => {
+                let file = snapshot_file::create_receiving(dir).await.map_err(|e| StorageIOError::write_snapshot(None, &e))?;
+                Ok(Box::new(SnapshotBuffer::File(file)))
+            }
+            None => Ok(Box::new(SnapshotBuffer::memory(Vec::new()))),
+        }
     }
 
     async fn install_snapshot(
@@ -162,21 +438,29 @@ impl RaftStateMachine<TypeConfig> for Arc<StateMachineStore> {
         meta: &SnapshotMeta<TypeConfig>,
         snapshot: Box<SnapshotDataOf<TypeConfig>>,
     ) -> Result<(), StorageError<NodeId>> {
-        let new_snapshot = StoredSnapshot {
-            meta: meta.clone(),
-            data: snapshot.into_inner(),
-        };
+        let read_error = |e: io::Error| StorageIOError::read_snapshot(Some(meta.signature()), &e);
+        let mut data = snapshot.into_reader().await.map_err(read_error)?;
 
         // Update the state machine.
-        let updated_state_machine_data = serde_json::from_slice(&new_snapshot.data)
-            .map_err(|e| StorageIOError::read_snapshot(Some(new_snapshot.meta.signature()), &e))?;
+        let snapshot_state = snapshot_format::decode(&mut data).map_err(read_error)?;
+        data.rewind().map_err(read_error)?;
+        let new_snapshot = self.persist_snapshot(meta, data)?;
+        if let Some(dir) = &self.snapshot_dir {
+            snapshot_file::remove_receiving(dir).map_err(|e| StorageIOError::write_snapshot(Some(meta.signature()), &e))?;
+        }
         let updated_state_machine = StateMachineData {
             last_applied_log: meta.last_log_id,
             last_membership: meta.last_membership.clone(),
-            data: updated_state_machine_data,
+            clock: snapshot_state.clock,
+            data: snapshot_state.data,
+            sessions: snapshot_state.sessions,
         };
         let mut state_machine = self.state_machine.write().await;
         *state_machine = updated_state_machine;
+        if let Some(last_log_id) = meta.last_log_id {
+            self.watch.skip_through(last_log_id.index);
+        }
+        self.applied_bytes_since_snapshot.store(0, Ordering::Relaxed);
 
         // Lock the current snapshot before releasing the lock on the state machine, to avoid a race
         // condition on the written snapshot
@@ -184,17 +468,24 @@ impl RaftStateMachine<TypeConfig> for Arc<StateMachineStore> {
         drop(state_machine);
 
         // Update current snapshot.
-        *current_snapshot = Some(new_snapshot);
+        *current_snapshot = new_snapshot;
         Ok(())
     }
 
     async fn get_current_snapshot(&mut self) -> Result<Option<Snapshot<TypeConfig>>, StorageError<NodeId>> {
-        match &*self.current_snapshot.read().await {
+        let current_snapshot = self.current_snapshot.read().await;
+        if let Some(dir) = &self.snapshot_dir {
+            let read_error = |e: io::Error| StorageIOError::read_snapshot(None, &e);
+            let Some((meta, file)) = snapshot_file::open(dir).map_err(read_error)? else { return Ok(None) };
+            let snapshot = SnapshotBuffer::saved(file).map_err(read_error)?;
+            return Ok(Some(Snapshot { meta, snapshot: Box::new(snapshot) }));
+        }
+        match &*current_snapshot {
             Some(snapshot) => {
                 let data = snapshot.data.clone();
                 Ok(Some(Snapshot {
                     meta: snapshot.meta.clone(),
-                    snapshot: Box::new(Cursor::new(data)),
+                    snapshot: Box::new(SnapshotBuffer::memory(data)),
                 }))
             }
             None => Ok(None),
@@ -0,0 +1,108 @@
+//! Provide `NodeLogStore`, the log storage a node runs with: either the in-memory `LogStore` or
+//! the durable `FileLogStore`, picked at startup.
+
+use std::fmt::Debug;
+use std::ops::RangeBounds;
+
+use openraft::storage::LogFlushed;
+use openraft::storage::RaftLogStorage;
+use openraft::LogId;
+use openraft::LogState;
+use openraft::RaftLogReader;
+use openraft::StorageError;
+use openraft::Vote;
+
+use crate::{NodeId, TypeConfig};
+
+use super::{FileLogStore, LogStore};
+
+#[derive(Clone, Debug)]
+pub enum NodeLogStore {
+    /// Lost when the node stops; for demonstrations and tests only.
+    Memory(LogStore),
+    /// Kept in a data directory across restarts.
+    File(FileLogStore),
+}
+
+impl Default for NodeLogStore {
+    fn default() -> Self {
+        NodeLogStore::Memory(LogStore::default())
+    }
+}
+
+impl RaftLogReader<TypeConfig> for NodeLogStore {
+    async fn try_get_log_entries<RB: RangeBounds<u64> + Clone + Debug>(
+        &mut self,
+        range: RB,
+    ) -> Result<Vec<<TypeConfig as openraft::RaftTypeConfig>::Entry>, StorageError<NodeId>> {
+        match self {
+            NodeLogStore::Memory(store) => store.try_get_log_entries(range).await,
+            NodeLogStore::File(store) => store.try_get_log_entries(range).await,
+        }
+    }
+
+    async fn read_vote(&mut self) -> Result<Option<Vote<NodeId>>, StorageError<NodeId>> {
+        match self {
+            NodeLogStore::Memory(store) => RaftLogReader::read_vote(store).await,
+            NodeLogStore::File(store) => RaftLogReader::read_vote(store).await,
+        }
+    }
+}
+
+impl RaftLogStorage<TypeConfig> for NodeLogStore {
+    type LogReader = Self;
+
+    async fn get_log_state(&mut self) -> Result<LogState<TypeConfig>, StorageError<NodeId>> {
+        match self {
+            NodeLogStore::Memory(store) => store.get_log_state().await,
+            NodeLogStore::File(store) => store.get_log_state().await,
+        }
+    }
+
+    async fn save_committed(&mut self, committed: Option<LogId<NodeId>>) -> Result<(), StorageError<NodeId>> {
+        match self {
+            NodeLogStore::Memory(store) => store.save_committed(committed).await,
+            NodeLogStore::File(store) => store.save_committed(committed).await,
+        }
+    }
+
+    async fn read_committed(&mut self) -> Result<Option<LogId<NodeId>>, StorageError<NodeId>> {
+        match self {
+            NodeLogStore::Memory(store) => store.read_committed().await,
+            NodeLogStore::File(store) => store.read_committed().await,
+        }
+    }
+
+    async fn save_vote(&mut self, vote: &Vote<NodeId>) -> Result<(), StorageError<NodeId>> {
+        match self {
+            NodeLogStore::Memory(store) => store.save_vote(vote).await,
+            NodeLogStore::File(store) => store.save_vote(vote).await,
+        }
+    }
+
+    async fn append<I>(&mut self, entries: I, callback: LogFlushed<TypeConfig>) -> Result<(), StorageError<NodeId>>
+    where I: IntoIterator<Item = <TypeConfig as openraft::RaftTypeConfig>::Entry> {
+        match self {
+            NodeLogStore::Memory(store) => store.append(entries, callback).await,
+            NodeLogStore::File(store) => store.append(entries, callback).await,
+        }
+    }
+
+    async fn truncate(&mut self, log_id: LogId<NodeId>) -> Result<(), StorageError<NodeId>> {
+        match self {
+            NodeLogStore::Memory(store) => store.truncate(log_id).await,
+            NodeLogStore::File(store) => store.truncate(log_id).await,
+        }
+    }
+
+    async fn purge(&mut self, log_id: LogId<NodeId>) -> Result<(), StorageError<NodeId>> {
+        match self {
+            NodeLogStore::Memory(store) => store.purge(log_id).await,
+            NodeLogStore::File(store) => store.purge(log_id).await,
+        }
+    }
+
+    async fn get_log_reader(&mut self) -> Self::LogReader {
+        self.clone()
+    }
+}
@@ -0,0 +1,111 @@
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+
+use super::Response;
+
+/// How many clients the state machine remembers. Past this, applying a request from a new client
+/// forgets the client that was idle the longest, whose later retries are applied again.
+pub const MAX_SESSIONS: usize = 4096;
+
+/// The last request applied for each client that sent `Request::Session`, so a retry of it is
+/// answered from here rather than applied twice. Part of every snapshot.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct Sessions {
+    clients: BTreeMap<u64, Session>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Session {
+    /// Sequence number of the last request applied for the client.
+    pub last_seq: u64,
+    /// What applying that request returned.
+    pub response: Response,
+    /// Log index of that request, to find the longest idle client.
+    pub last_index: u64,
+}
+
+impl Sessions {
+    /// The response to a request the client already sent, `None` if `seq` is new. A request older
+    /// than the last one gets an empty response, its own is no longer kept.
+    pub fn duplicate(&self, client_id: u64, seq: u64) -> Option<Response> {
+        let session = self.clients.get(&client_id)?;
+        if seq > session.last_seq {
+            return None;
+        }
+        let response = if seq == session.last_seq { session.response.clone() } else { Response::default() };
+        Some(Response { duplicate: true, ..response })
+    }
+
+    pub fn record(&mut self, client_id: u64, seq: u64, response: &Response, log_index: u64) {
+        if !self.clients.contains_key(&client_id) && self.clients.len() >= MAX_SESSIONS {
+            // Deterministic, ties go to the lowest client id, so every replica evicts the same client.
+            let idle = self.clients.iter().min_by_key(|(_, session)| session.last_index).map(|(client_id, _)| *client_id);
+            if let Some(idle) = idle {
+                self.clients.remove(&idle);
+            }
+        }
+        let session = Session { last_seq: seq, response: response.clone(), last_index: log_index };
+        self.clients.insert(client_id, session);
+    }
+
+    pub fn len(&self) -> usize {
+        self.clients.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.clients.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&u64, &Session)> {
+        self.clients.iter()
+    }
+}
+
+impl FromIterator<(u64, Session)> for Sessions {
+    fn from_iter<I: IntoIterator<Item = (u64, Session)>>(iter: I) -> Self {
+        Sessions { clients: iter.into_iter().collect() }
+    }
+}
+
+#[test]
+fn test_sessions_duplicate() {
+    let mut sessions = Sessions::default();
+    assert!(sessions.duplicate(1, 1).is_none());
+
+    sessions.record(1, 2, &Response::value(Some(ts_core::tuple::Tuple::builder().integer(5).build())), 10);
+    let retry = sessions.duplicate(1, 2).unwrap();
+    assert!(retry.duplicate);
+    assert_eq!(Some(ts_core::tuple::Tuple::builder().integer(5).build()), retry.value);
+
+    // An older request no longer has its response kept.
+    let older = sessions.duplicate(1, 1).unwrap();
+    assert!(older.duplicate);
+    assert!(older.value.is_none());
+
+    assert!(sessions.duplicate(1, 3).is_none());
+    assert!(sessions.duplicate(2, 2).is_none());
+}
+
+#[test]
+fn test_sessions_eviction() {
+    let mut sessions = Sessions::default();
+    for client_id in 0..MAX_SESSIONS as u64 {
+        // Client 0 is the last to be active.
+        let log_index = if client_id == 0 { MAX_SESSIONS as u64 } else { client_id };
+        sessions.record(client_id, 1, &Response::default(), log_index);
+    }
+    assert_eq!(MAX_SESSIONS, sessions.len());
+
+    // A known client does not evict anyone.
+    sessions.record(0, 2, &Response::default(), MAX_SESSIONS as u64 + 1);
+    assert_eq!(MAX_SESSIONS, sessions.len());
+
+    // A new one evicts the longest idle.
+    sessions.record(MAX_SESSIONS as u64, 1, &Response::default(), MAX_SESSIONS as u64 + 2);
+    assert_eq!(MAX_SESSIONS, sessions.len());
+    assert!(sessions.duplicate(1, 1).is_none());
+    assert!(sessions.duplicate(0, 2).is_some());
+    assert!(sessions.duplicate(2, 1).is_some());
+    assert!(sessions.duplicate(MAX_SESSIONS as u64, 1).is_some());
+}
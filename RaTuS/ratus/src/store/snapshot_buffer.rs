@@ -0,0 +1,141 @@
+//! The snapshot data openraft streams from and into: in memory for snapshots this node holds
+//! only in memory, in a file for one being received or already saved by a node with a data
+//! directory, so a large snapshot is neither buffered in memory while it arrives in chunks nor
+//! read into memory to be sent.
+
+use std::io::{self, BufReader, Cursor, Read, Seek, SeekFrom};
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use tokio::fs::File;
+use tokio::io::{AsyncRead, AsyncSeek, AsyncSeekExt, AsyncWrite, AsyncWriteExt, ReadBuf};
+
+#[derive(Debug)]
+pub enum SnapshotBuffer {
+    Memory(Cursor<Vec<u8>>),
+    File(File),
+    /// A saved snapshot file whose data begins at `start`, past its header. Positions are given
+    /// relative to `start`, as for the other kinds.
+    Saved { file: File, start: u64 },
+}
+
+impl SnapshotBuffer {
+    pub fn memory(data: Vec<u8>) -> Self {
+        SnapshotBuffer::Memory(Cursor::new(data))
+    }
+
+    /// The data of `file` from where it is positioned on.
+    pub fn saved(mut file: std::fs::File) -> io::Result<Self> {
+        let start = file.stream_position()?;
+        Ok(SnapshotBuffer::Saved { file: File::from_std(file), start })
+    }
+
+    /// The data from its start, wherever the buffer is positioned, for blocking reads. A file is
+    /// read through a buffer rather than into memory.
+    pub async fn into_reader(self) -> io::Result<SnapshotReader> {
+        match self {
+            SnapshotBuffer::Memory(mut cursor) => {
+                cursor.set_position(0);
+                Ok(SnapshotReader::Memory(cursor))
+            }
+            SnapshotBuffer::File(mut file) => {
+                file.flush().await?;
+                file.seek(SeekFrom::Start(0)).await?;
+                Ok(SnapshotReader::File(BufReader::new(file.into_std().await), 0))
+            }
+            SnapshotBuffer::Saved { mut file, start } => {
+                file.seek(SeekFrom::Start(start)).await?;
+                Ok(SnapshotReader::File(BufReader::new(file.into_std().await), start))
+            }
+        }
+    }
+}
+
+/// A [`SnapshotBuffer`] done receiving, read with blocking calls. A file's data begins at the
+/// given offset.
+#[derive(Debug)]
+pub enum SnapshotReader {
+    Memory(Cursor<Vec<u8>>),
+    File(BufReader<std::fs::File>, u64),
+}
+
+impl Read for SnapshotReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            SnapshotReader::Memory(cursor) => cursor.read(buf),
+            SnapshotReader::File(file, _) => file.read(buf),
+        }
+    }
+}
+
+impl Seek for SnapshotReader {
+    fn seek(&mut self, position: SeekFrom) -> io::Result<u64> {
+        match self {
+            SnapshotReader::Memory(cursor) => Seek::seek(cursor, position),
+            SnapshotReader::File(file, start) => {
+                let position = Seek::seek(file, from_data(position, *start))?;
+                Ok(position.saturating_sub(*start))
+            }
+        }
+    }
+}
+
+impl AsyncRead for SnapshotBuffer {
+    fn poll_read(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            SnapshotBuffer::Memory(cursor) => Pin::new(cursor).poll_read(cx, buf),
+            SnapshotBuffer::File(file) | SnapshotBuffer::Saved { file, .. } => Pin::new(file).poll_read(cx, buf),
+        }
+    }
+}
+
+impl AsyncWrite for SnapshotBuffer {
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        match self.get_mut() {
+            SnapshotBuffer::Memory(cursor) => Pin::new(cursor).poll_write(cx, buf),
+            SnapshotBuffer::File(file) | SnapshotBuffer::Saved { file, .. } => Pin::new(file).poll_write(cx, buf),
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            SnapshotBuffer::Memory(cursor) => Pin::new(cursor).poll_flush(cx),
+            SnapshotBuffer::File(file) | SnapshotBuffer::Saved { file, .. } => Pin::new(file).poll_flush(cx),
+        }
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            SnapshotBuffer::Memory(cursor) => Pin::new(cursor).poll_shutdown(cx),
+            SnapshotBuffer::File(file) | SnapshotBuffer::Saved { file, .. } => Pin::new(file).poll_shutdown(cx),
+        }
+    }
+}
+
+impl AsyncSeek for SnapshotBuffer {
+    fn start_seek(self: Pin<&mut Self>, position: SeekFrom) -> io::Result<()> {
+        match self.get_mut() {
+            SnapshotBuffer::Memory(cursor) => Pin::new(cursor).start_seek(position),
+            SnapshotBuffer::File(file) => Pin::new(file).start_seek(position),
+            SnapshotBuffer::Saved { file, start } => Pin::new(file).start_seek(from_data(position, *start)),
+        }
+    }
+
+    fn poll_complete(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<u64>> {
+        match self.get_mut() {
+            SnapshotBuffer::Memory(cursor) => Pin::new(cursor).poll_complete(cx),
+            SnapshotBuffer::File(file) => Pin::new(file).poll_complete(cx),
+            SnapshotBuffer::Saved { file, start } => {
+                Pin::new(file).poll_complete(cx).map_ok(|position| position.saturating_sub(*start))
+            }
+        }
+    }
+}
+
+/// `position`, given relative to data beginning at `start`, as a position in the whole file.
+fn from_data(position: SeekFrom, start: u64) -> SeekFrom {
+    match position {
+        SeekFrom::Start(offset) => SeekFrom::Start(start.saturating_add(offset)),
+        relative => relative,
+    }
+}
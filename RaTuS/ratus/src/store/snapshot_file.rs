@@ -0,0 +1,74 @@
+//! Keeps the latest snapshot of a node in its data directory, as `snapshots/current.snapshot`:
+//! `[meta length: u32 LE][meta: JSON SnapshotMeta][snapshot data]`, replaced atomically with a
+//! write-fsync-rename. A snapshot being received from the leader is streamed into
+//! `snapshots/receiving.snapshot` until it is installed.
+
+use std::fs::{self, File};
+use std::io::{self, ErrorKind, Read, Write};
+use std::path::{Path, PathBuf};
+
+use openraft::SnapshotMeta;
+
+use super::file_log_store::sync_dir;
+use crate::TypeConfig;
+
+const SNAPSHOT_DIR: &str = "snapshots";
+const SNAPSHOT_FILE: &str = "current.snapshot";
+const RECEIVING_FILE: &str = "receiving.snapshot";
+
+/// The directory snapshots of the node with `data_dir` are kept in, created if needed.
+pub(super) fn snapshot_dir(data_dir: &Path) -> io::Result<PathBuf> {
+    let dir = data_dir.join(SNAPSHOT_DIR);
+    fs::create_dir_all(&dir)?;
+    Ok(dir)
+}
+
+/// Saves the snapshot of `meta` with the data read from `data`, copied over without holding it all.
+pub(super) fn save(dir: &Path, meta: &SnapshotMeta<TypeConfig>, data: &mut impl Read) -> io::Result<()> {
+    let meta = serde_json::to_vec(meta).map_err(io::Error::from)?;
+    let meta_len = u32::try_from(meta.len()).map_err(|_| io::Error::new(ErrorKind::InvalidInput, "snapshot meta too large"))?;
+
+    let temporary_path = dir.join(format!("{}.tmp", SNAPSHOT_FILE));
+    let mut file = File::create(&temporary_path)?;
+    file.write_all(&meta_len.to_le_bytes())?;
+    file.write_all(&meta)?;
+    io::copy(data, &mut file)?;
+    file.sync_all()?;
+    fs::rename(&temporary_path, dir.join(SNAPSHOT_FILE))?;
+    sync_dir(dir)
+}
+
+/// An empty file to receive the chunks of a snapshot into, replacing any partial one.
+pub(super) async fn create_receiving(dir: &Path) -> io::Result<tokio::fs::File> {
+    tokio::fs::OpenOptions::new().read(true).write(true).create(true).truncate(true).open(dir.join(RECEIVING_FILE)).await
+}
+
+/// Drops the file a snapshot was received into, once it is installed.
+pub(super) fn remove_receiving(dir: &Path) -> io::Result<()> {
+    match fs::remove_file(dir.join(RECEIVING_FILE)) {
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(()),
+        result => result,
+    }
+}
+
+/// The meta of the saved snapshot and its file, positioned at the data. `None` if there is none
+/// yet. A later save replaces the file but leaves an open one as it was.
+pub(super) fn open(dir: &Path) -> io::Result<Option<(SnapshotMeta<TypeConfig>, File)>> {
+    let mut file = match File::open(dir.join(SNAPSHOT_FILE)) {
+        Ok(file) => file,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e),
+    };
+
+    let truncated = || io::Error::new(ErrorKind::InvalidData, "truncated snapshot file");
+    let mut meta_len = [0; 4];
+    file.read_exact(&mut meta_len).map_err(|e| if e.kind() == ErrorKind::UnexpectedEof { truncated() } else { e })?;
+    let meta_len = u64::from(u32::from_le_bytes(meta_len));
+    let mut meta_bytes = Vec::new();
+    file.by_ref().take(meta_len).read_to_end(&mut meta_bytes)?;
+    if (meta_bytes.len() as u64) < meta_len {
+        return Err(truncated());
+    }
+    let meta = serde_json::from_slice(&meta_bytes).map_err(io::Error::from)?;
+    Ok(Some((meta, file)))
+}
@@ -0,0 +1,451 @@
+//! Binary encoding of [`SnapshotState`], the data part of a snapshot:
+//!
+//! ```text
+//! header: [magic "RTSN"][version: u8][flags: u8]
+//! body:     [clock][policy][generator state][entry count] entry* [session count] session*
+//! entry:    [expires_at + 1, 0 if none][arity] field*
+//! field:    [tag: u8] then a bool byte, a zigzag integer, an f64 LE, or [length] UTF-8 bytes
+//! session:  [client id][last seq][last index] response
+//! response: [value: optional tuple][count] tuple* [count] optional tuple* [aborted_at + 1, 0 if none]
+//! ```
+//!
+//! Numbers in the body are LEB128 varints, an optional tuple is a `0` byte or a `1` byte and the
+//! tuple. With [`FLAG_COMPRESSED`] set the body is LZ4 compressed, prefixed with its uncompressed
+//! size. Only live tuples are written, in write order.
+//!
+//! Version 1 bodies end after the entries, with no sessions. Snapshots written before this format
+//! are JSON, recognised by the missing magic and still read, see [`decode_json`].
+
+use std::io::{self, ErrorKind, Read};
+
+use serde::Deserialize;
+
+use ts_core::{
+    indexed_store::{IndexedStore, StoreImage},
+    mutex_store::MutexStore,
+    policy::MatchPolicy,
+    tuple::Tuple,
+    types::Types,
+};
+
+use super::{session::{Session, Sessions}, Response, SnapshotState, StateMachineData, TupleStore};
+
+const MAGIC: &[u8; 4] = b"RTSN";
+const VERSION: u8 = 2;
+/// The last version without sessions.
+const VERSION_WITHOUT_SESSIONS: u8 = 1;
+const HEADER_LEN: usize = MAGIC.len() + 2;
+
+/// The body is LZ4 compressed.
+const FLAG_COMPRESSED: u8 = 0b0000_0001;
+
+const POLICY_OLDEST_FIRST: u8 = 0;
+const POLICY_NEWEST_FIRST: u8 = 1;
+const POLICY_RANDOM: u8 = 2;
+
+const TAG_BOOLEAN: u8 = 0;
+const TAG_INTEGER: u8 = 1;
+const TAG_FLOAT: u8 = 2;
+const TAG_STRING: u8 = 3;
+
+/// How many entries or sessions to make room for up front. The counts come from the snapshot, so
+/// a corrupt one cannot make us over-allocate.
+const MAX_PREALLOCATED: usize = 4096;
+
+pub(super) fn encode(state_machine: &StateMachineData, compress: bool) -> io::Result<Vec<u8>> {
+    let image = StoreImage::from(state_machine.data.to_inner().map_err(|e| invalid_data(format!("{:?}", e)))?);
+
+    let mut body = Vec::new();
+    put_varint(&mut body, state_machine.clock);
+    match image.policy {
+        MatchPolicy::OldestFirst => body.push(POLICY_OLDEST_FIRST),
+        MatchPolicy::NewestFirst => body.push(POLICY_NEWEST_FIRST),
+        MatchPolicy::Random { seed } => {
+            body.push(POLICY_RANDOM);
+            put_varint(&mut body, seed);
+        }
+    }
+    put_varint(&mut body, image.generator_state);
+    put_varint(&mut body, image.entries.len() as u64);
+    for (tuple, expires_at) in image.entries.iter() {
+        put_varint(&mut body, expires_at.map_or(0, |expires_at| expires_at + 1));
+        put_tuple(&mut body, tuple);
+    }
+    put_varint(&mut body, state_machine.sessions.len() as u64);
+    for (client_id, session) in state_machine.sessions.iter() {
+        put_varint(&mut body, *client_id);
+        put_varint(&mut body, session.last_seq);
+        put_varint(&mut body, session.last_index);
+        put_response(&mut body, &session.response);
+    }
+
+    let mut bytes = Vec::with_capacity(HEADER_LEN + body.len());
+    bytes.extend_from_slice(MAGIC);
+    bytes.push(VERSION);
+    if compress {
+        bytes.push(FLAG_COMPRESSED);
+        bytes.extend_from_slice(&lz4_flex::compress_prepend_size(&body));
+    } else {
+        bytes.push(0);
+        bytes.extend_from_slice(&body);
+    }
+    Ok(bytes)
+}
+
+/// Reads a snapshot in this format or in the legacy JSON one. An uncompressed body is decoded as
+/// it is read; a compressed or JSON one is read whole first.
+pub(super) fn decode(mut reader: impl Read) -> io::Result<SnapshotState> {
+    let mut header = [0; HEADER_LEN];
+    let header_len = read_up_to(&mut reader, &mut header)?;
+    if !header[..header_len].starts_with(MAGIC) {
+        let mut bytes = header[..header_len].to_vec();
+        reader.read_to_end(&mut bytes)?;
+        return decode_json(&bytes);
+    }
+    if header_len < HEADER_LEN {
+        return Err(invalid_data("truncated snapshot header"));
+    }
+    let (version, flags) = (header[MAGIC.len()], header[MAGIC.len() + 1]);
+    if version != VERSION && version != VERSION_WITHOUT_SESSIONS {
+        return Err(invalid_data(format!("unsupported snapshot version {}", version)));
+    }
+    if flags & !FLAG_COMPRESSED != 0 {
+        return Err(invalid_data(format!("unknown snapshot flags {:#010b}", flags)));
+    }
+
+    if flags & FLAG_COMPRESSED != 0 {
+        let mut compressed = Vec::new();
+        reader.read_to_end(&mut compressed)?;
+        let body = lz4_flex::decompress_size_prepended(&compressed).map_err(invalid_data)?;
+        decode_body(Reader { inner: body.as_slice() }, version)
+    } else {
+        decode_body(Reader { inner: reader }, version)
+    }
+}
+
+fn decode_body<R: Read>(mut reader: Reader<R>, version: u8) -> io::Result<SnapshotState> {
+    let clock = reader.varint()?;
+    let policy = match reader.byte()? {
+        POLICY_OLDEST_FIRST => MatchPolicy::OldestFirst,
+        POLICY_NEWEST_FIRST => MatchPolicy::NewestFirst,
+        POLICY_RANDOM => MatchPolicy::Random { seed: reader.varint()? },
+        tag => return Err(invalid_data(format!("unknown match policy {}", tag))),
+    };
+    let generator_state = reader.varint()?;
+    let count = reader.varint()?;
+    let mut entries = Vec::with_capacity((count as usize).min(MAX_PREALLOCATED));
+    for _ in 0..count {
+        let expires_at = reader.varint()?.checked_sub(1);
+        entries.push((reader.tuple()?, expires_at));
+    }
+    let sessions = if version == VERSION_WITHOUT_SESSIONS {
+        Sessions::default()
+    } else {
+        let count = reader.varint()?;
+        let mut sessions = Vec::with_capacity((count as usize).min(MAX_PREALLOCATED));
+        for _ in 0..count {
+            let client_id = reader.varint()?;
+            let last_seq = reader.varint()?;
+            let last_index = reader.varint()?;
+            sessions.push((client_id, Session { last_seq, last_index, response: reader.response()? }));
+        }
+        sessions.into_iter().collect()
+    };
+    if !reader.at_end()? {
+        return Err(invalid_data("trailing bytes after snapshot entries"));
+    }
+
+    let image = StoreImage { policy, generator_state, entries };
+    Ok(SnapshotState { clock, data: MutexStore::new(IndexedStore::from(image)), sessions })
+}
+
+/// Fills `buf` from `reader`, or as much of it as there is. Returns how many bytes were read.
+fn read_up_to(reader: &mut impl Read, buf: &mut [u8]) -> io::Result<usize> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        match reader.read(&mut buf[filled..]) {
+            Ok(0) => break,
+            Ok(read) => filled += read,
+            Err(e) if e.kind() == ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(filled)
+}
+
+/// The JSON snapshots taken before this format. In order of age:
+///
+/// - the bare tuple store of the first releases, a `MutexStore<VecStore>` whose freed slots are
+///   `null`, rebuilt from its live tuples;
+/// - the bare `TupleStore`, once it was an [`IndexedStore`];
+/// - the serde form of [`SnapshotState`], once the clock was added.
+fn decode_json(bytes: &[u8]) -> io::Result<SnapshotState> {
+    /// `MutexStore<S>` as serde writes it.
+    #[derive(Deserialize)]
+    struct BareStore<S> {
+        store: S,
+    }
+
+    /// The `VecStore` of the first releases: tuples without expiry, `null` where one was taken.
+    #[derive(Deserialize)]
+    struct FirstVecStore {
+        inner: Vec<Option<Tuple>>,
+    }
+
+    let error = match serde_json::from_slice::<SnapshotState>(bytes) {
+        Ok(state) => return Ok(state),
+        Err(error) => error,
+    };
+    if let Ok(BareStore { store }) = serde_json::from_slice::<BareStore<FirstVecStore>>(bytes) {
+        let tuples: Vec<Tuple> = store.inner.into_iter().flatten().collect();
+        return Ok(SnapshotState { data: MutexStore::new(IndexedStore::from(tuples)), ..Default::default() });
+    }
+    if let Ok(data) = serde_json::from_slice::<TupleStore>(bytes) {
+        return Ok(SnapshotState { data, ..Default::default() });
+    }
+    Err(io::Error::from(error))
+}
+
+fn put_tuple(bytes: &mut Vec<u8>, tuple: &Tuple) {
+    put_varint(bytes, tuple.len() as u64);
+    for index in 0..tuple.len() {
+        match &tuple[index] {
+            Types::Boolean(value) => {
+                bytes.push(TAG_BOOLEAN);
+                bytes.push(*value as u8);
+            }
+            Types::Integer(value) => {
+                bytes.push(TAG_INTEGER);
+                put_varint(bytes, ((value << 1) ^ (value >> 63)) as u64);
+            }
+            Types::Float(value) => {
+                bytes.push(TAG_FLOAT);
+                bytes.extend_from_slice(&value.to_le_bytes());
+            }
+            Types::String(value) => {
+                bytes.push(TAG_STRING);
+                put_varint(bytes, value.len() as u64);
+                bytes.extend_from_slice(value.as_bytes());
+            }
+        }
+    }
+}
+
+fn put_optional_tuple(bytes: &mut Vec<u8>, tuple: Option<&Tuple>) {
+    match tuple {
+        Some(tuple) => {
+            bytes.push(1);
+            put_tuple(bytes, tuple);
+        }
+        None => bytes.push(0),
+    }
+}
+
+/// The `duplicate` flag is not kept: it is set on the copy handed out for a retry.
+fn put_response(bytes: &mut Vec<u8>, response: &Response) {
+    put_optional_tuple(bytes, response.value.as_ref());
+    put_varint(bytes, response.values.len() as u64);
+    for tuple in response.values.iter() {
+        put_tuple(bytes, tuple);
+    }
+    put_varint(bytes, response.results.len() as u64);
+    for result in response.results.iter() {
+        put_optional_tuple(bytes, result.as_ref());
+    }
+    put_varint(bytes, response.aborted_at.map_or(0, |op_index| op_index as u64 + 1));
+}
+
+fn put_varint(bytes: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        bytes.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    bytes.push(value as u8);
+}
+
+struct Reader<R> {
+    inner: R,
+}
+
+impl<R: Read> Reader<R> {
+    fn bytes(&mut self, len: usize) -> io::Result<Vec<u8>> {
+        // Read in pieces rather than trusting `len` with an allocation.
+        let mut bytes = Vec::new();
+        self.inner.by_ref().take(len as u64).read_to_end(&mut bytes)?;
+        if bytes.len() < len {
+            return Err(invalid_data("truncated snapshot body"));
+        }
+        Ok(bytes)
+    }
+
+    fn byte(&mut self) -> io::Result<u8> {
+        let mut byte = [0];
+        self.exact(&mut byte)?;
+        Ok(byte[0])
+    }
+
+    fn exact(&mut self, buf: &mut [u8]) -> io::Result<()> {
+        self.inner.read_exact(buf).map_err(|e| match e.kind() {
+            ErrorKind::UnexpectedEof => invalid_data("truncated snapshot body"),
+            _ => e,
+        })
+    }
+
+    fn at_end(&mut self) -> io::Result<bool> {
+        Ok(read_up_to(&mut self.inner, &mut [0])? == 0)
+    }
+
+    fn varint(&mut self) -> io::Result<u64> {
+        let mut value = 0u64;
+        for shift in (0..64).step_by(7) {
+            let byte = self.byte()?;
+            value |= u64::from(byte & 0x7f) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Err(invalid_data("varint longer than 64 bits"))
+    }
+
+    fn tuple(&mut self) -> io::Result<Tuple> {
+        let arity = self.varint()?;
+        let mut builder = Tuple::builder();
+        for _ in 0..arity {
+            builder = match self.byte()? {
+                TAG_BOOLEAN => builder.boolean(self.byte()? != 0),
+                TAG_INTEGER => {
+                    let zigzag = self.varint()?;
+                    builder.integer((zigzag >> 1) as i64 ^ -((zigzag & 1) as i64))
+                }
+                TAG_FLOAT => {
+                    let mut value = [0; 8];
+                    self.exact(&mut value)?;
+                    builder.float(f64::from_le_bytes(value))
+                }
+                TAG_STRING => {
+                    let len = self.varint()? as usize;
+                    let value = String::from_utf8(self.bytes(len)?).map_err(invalid_data)?;
+                    builder.string(&value)
+                }
+                tag => return Err(invalid_data(format!("unknown field type {}", tag))),
+            };
+        }
+        Ok(builder.build())
+    }
+
+    fn optional_tuple(&mut self) -> io::Result<Option<Tuple>> {
+        match self.byte()? {
+            0 => Ok(None),
+            1 => Ok(Some(self.tuple()?)),
+            flag => Err(invalid_data(format!("invalid optional tuple flag {}", flag))),
+        }
+    }
+
+    fn response(&mut self) -> io::Result<Response> {
+        let value = self.optional_tuple()?;
+        let values = (0..self.varint()?).map(|_| self.tuple()).collect::<io::Result<_>>()?;
+        let results = (0..self.varint()?).map(|_| self.optional_tuple()).collect::<io::Result<_>>()?;
+        let aborted_at = self.varint()?.checked_sub(1).map(|op_index| op_index as usize);
+        Ok(Response { value, values, results, aborted_at, duplicate: false })
+    }
+}
+
+fn invalid_data<E>(error: E) -> io::Error
+where E: Into<Box<dyn std::error::Error + Send + Sync>> {
+    io::Error::new(ErrorKind::InvalidData, error)
+}
+
+#[cfg(test)]
+fn sample_state_machine() -> StateMachineData {
+    use ts_core::store::Store;
+
+    let mut data = MutexStore::new(IndexedStore::with_policy(MatchPolicy::Random { seed: 3 }));
+    data.write(&Tuple::builder().string("job").integer(-7).float(1.5).boolean(true).build()).unwrap();
+    data.write_with_expiry(&Tuple::builder().string("lease").build(), 5_000).unwrap();
+    data.get(&ts_core::query_tuple::QueryTuple::builder().string("lease").build()).unwrap();
+    data.write_with_expiry(&Tuple::builder().string("lease").integer(2).build(), 6_000).unwrap();
+
+    let mut sessions = Sessions::default();
+    sessions.record(9, 4, &Response::value(Some(Tuple::builder().integer(1).build())), 12);
+    StateMachineData { clock: 4_000, data, sessions, ..Default::default() }
+}
+
+#[cfg(test)]
+fn assert_same_state(expected: &StateMachineData, actual: &SnapshotState) {
+    assert_eq!(expected.clock, actual.clock);
+    assert_eq!(StoreImage::from(expected.data.to_inner().unwrap()), StoreImage::from(actual.data.to_inner().unwrap()));
+    let sessions = |sessions: &Sessions| -> Vec<(u64, u64, u64, Option<Tuple>)> {
+        sessions.iter().map(|(client_id, session)| (*client_id, session.last_seq, session.last_index, session.response.value.clone())).collect()
+    };
+    assert_eq!(sessions(&expected.sessions), sessions(&actual.sessions));
+}
+
+#[test]
+fn test_snapshot_roundtrip() {
+    let state_machine = sample_state_machine();
+    for compress in [false, true] {
+        let bytes = encode(&state_machine, compress).unwrap();
+        assert_eq!(VERSION, bytes[MAGIC.len()]);
+        assert_same_state(&state_machine, &decode(bytes.as_slice()).unwrap());
+    }
+}
+
+#[test]
+fn test_snapshot_corrupt() {
+    let bytes = encode(&sample_state_machine(), false).unwrap();
+    for len in [MAGIC.len() + 1, HEADER_LEN, bytes.len() - 1] {
+        let error = decode(&bytes[..len]).unwrap_err();
+        assert_eq!(ErrorKind::InvalidData, error.kind());
+    }
+    let mut trailing = bytes.clone();
+    trailing.push(0);
+    assert_eq!(ErrorKind::InvalidData, decode(trailing.as_slice()).unwrap_err().kind());
+}
+
+#[test]
+fn test_snapshot_version_without_sessions() {
+    let mut state_machine = sample_state_machine();
+    state_machine.sessions = Sessions::default();
+    let mut bytes = encode(&state_machine, false).unwrap();
+    // A version 1 body is a version 2 one without the session count.
+    bytes[MAGIC.len()] = VERSION_WITHOUT_SESSIONS;
+    assert_eq!(Some(0), bytes.pop());
+    assert_same_state(&state_machine, &decode(bytes.as_slice()).unwrap());
+}
+
+#[test]
+fn test_snapshot_serde_json() {
+    let state_machine = sample_state_machine();
+    let json = format!(
+        r#"{{"clock":{},"data":{},"sessions":{}}}"#,
+        state_machine.clock,
+        serde_json::to_string(&state_machine.data).unwrap(),
+        serde_json::to_string(&state_machine.sessions).unwrap(),
+    );
+    assert_same_state(&state_machine, &decode(json.as_bytes()).unwrap());
+
+    // Before the clock, the snapshot was the bare store.
+    let json = serde_json::to_vec(&state_machine.data).unwrap();
+    let state = decode(json.as_slice()).unwrap();
+    assert_eq!(0, state.clock);
+    assert_eq!(StoreImage::from(state_machine.data.to_inner().unwrap()), StoreImage::from(state.data.to_inner().unwrap()));
+}
+
+#[test]
+fn test_snapshot_first_vec_store_json() {
+    let json = r#"{"store":{"inner":[{"tuple":[{"String":"a"},{"Integer":1}]},null,{"tuple":[{"String":"b"}]}],"tuple_count":2,"compact_margin":0.9}}"#;
+    let state = decode(json.as_bytes()).unwrap();
+    assert_eq!(0, state.clock);
+    assert!(state.sessions.is_empty());
+    let image = StoreImage::from(state.data.to_inner().unwrap());
+    assert_eq!(MatchPolicy::OldestFirst, image.policy);
+    assert_eq!(
+        vec![
+            (Tuple::builder().string("a").integer(1).build(), None),
+            (Tuple::builder().string("b").build(), None),
+        ],
+        image.entries
+    );
+
+    assert!(decode(&br#"{"store":{"inner":[7]}}"#[..]).is_err());
+}
@@ -0,0 +1,121 @@
+use std::io;
+
+use openraft::{StorageError, StorageIOError};
+use serde::{Deserialize, Serialize};
+
+use ts_core::{query_tuple::QueryTuple, store::Store, tuple::Tuple};
+
+use super::{Response, TupleStore};
+use crate::NodeId;
+
+/// One step of a `Request::Transaction`. Every op sees the effects of the ops before it.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub enum Op {
+    Write { tuple: Tuple },
+    /// Takes one tuple matching `query`. The whole transaction aborts if there is none.
+    Take { query: QueryTuple },
+    /// Copies one tuple matching `query`, or `None`. Never aborts the transaction.
+    Read { query: QueryTuple },
+}
+
+/// Applies `ops` in order, all or nothing.
+///
+/// If a `Take` finds no match, the store is put back exactly as it was before the transaction,
+/// expiry times, write order and the generator state of [`MatchPolicy::Random`] included, and the
+/// response names the failing op. Every replica then keeps the same store. A poisoned store lock
+/// is a storage error: no op was applied, and answering "aborted" would hide it from the caller.
+///
+/// [`MatchPolicy::Random`]: ts_core::policy::MatchPolicy::Random
+pub(super) fn apply(data: &mut TupleStore, ops: &[Op]) -> Result<Response, StorageError<NodeId>> {
+    let outcome = data.atomically(|store| {
+        let mut results = Vec::with_capacity(ops.len());
+        for (index, op) in ops.iter().enumerate() {
+            let result = match op {
+                Op::Write { tuple } => store.write(tuple).map(|()| None),
+                Op::Take { query } => store.get(query),
+                Op::Read { query } => store.read(query),
+            };
+            match result {
+                Ok(None) if matches!(op, Op::Take { .. }) => return Err(index),
+                Ok(result) => results.push(result),
+                Err(_) => return Err(index),
+            }
+        }
+        Ok(results)
+    });
+    match outcome {
+        Ok(Ok(results)) => Ok(Response::committed(results)),
+        Ok(Err(index)) => Ok(Response::aborted(index)),
+        Err(e) => Err(StorageIOError::write_state_machine(&io::Error::other(format!("tuple store: {e:?}"))).into()),
+    }
+}
+
+#[test]
+fn test_transaction_commit() {
+    let mut data = TupleStore::default();
+    data.write(&Tuple::builder().string("in").integer(1).build()).unwrap();
+    let ops = vec![
+        Op::Take { query: QueryTuple::builder().string("in").any_integer().build() },
+        Op::Write { tuple: Tuple::builder().string("out").integer(1).build() },
+        Op::Read { query: QueryTuple::builder().string("out").any_integer().build() },
+    ];
+
+    let response = apply(&mut data, &ops).unwrap();
+    assert_eq!(None, response.aborted_at);
+    assert_eq!(
+        vec![
+            Some(Tuple::builder().string("in").integer(1).build()),
+            None,
+            Some(Tuple::builder().string("out").integer(1).build()),
+        ],
+        response.results
+    );
+    assert_eq!(1, data.size().unwrap());
+}
+
+#[test]
+fn test_transaction_abort_keeps_expiries() {
+    use ts_core::indexed_store::StoreImage;
+
+    let mut data = TupleStore::default();
+    data.write_with_expiry(&Tuple::builder().string("lease").integer(1).build(), 1_000).unwrap();
+    data.write(&Tuple::builder().string("job").integer(1).build()).unwrap();
+    let before = StoreImage::from(data.to_inner().unwrap());
+
+    // Takes the leased tuple and writes an identical one without expiry before aborting.
+    let ops = vec![
+        Op::Take { query: QueryTuple::builder().string("lease").any_integer().build() },
+        Op::Write { tuple: Tuple::builder().string("lease").integer(1).build() },
+        Op::Take { query: QueryTuple::builder().string("missing").build() },
+    ];
+    let response = apply(&mut data, &ops).unwrap();
+    assert_eq!(Some(2), response.aborted_at);
+    assert!(response.results.is_empty());
+    assert_eq!(before, StoreImage::from(data.to_inner().unwrap()));
+
+    data.expire(1_000).unwrap();
+    assert_eq!(1, data.size().unwrap());
+}
+
+#[test]
+fn test_transaction_abort_keeps_random_state() {
+    use ts_core::{indexed_store::{IndexedStore, StoreImage}, mutex_store::MutexStore, policy::MatchPolicy};
+
+    let mut data = MutexStore::new(IndexedStore::with_policy(MatchPolicy::Random { seed: 42 }));
+    for value in 0..8 {
+        data.write(&Tuple::builder().string("job").integer(value).build()).unwrap();
+    }
+    let mut untouched = MutexStore::new(data.to_inner().unwrap());
+    let before = StoreImage::from(data.to_inner().unwrap());
+
+    let ops = vec![
+        Op::Take { query: QueryTuple::builder().string("job").any_integer().build() },
+        Op::Take { query: QueryTuple::builder().string("missing").build() },
+    ];
+    assert_eq!(Some(1), apply(&mut data, &ops).unwrap().aborted_at);
+    assert_eq!(before, StoreImage::from(data.to_inner().unwrap()));
+
+    // The next pick is the one a store that never saw the transaction makes.
+    let query = QueryTuple::builder().string("job").any_integer().build();
+    assert_eq!(untouched.get(&query).unwrap(), data.get(&query).unwrap());
+}
@@ -0,0 +1,186 @@
+//! Tuple events of applied requests, for the `watch` endpoint. The state machine keeps the latest
+//! [`HISTORY_LEN`] events in memory, so a client that reconnects can resume where it left off, and
+//! hands every new one to the live subscribers.
+
+use std::{collections::VecDeque, fmt, str::FromStr, sync::Mutex};
+
+use serde::{Deserialize, Serialize};
+use tokio::sync::broadcast;
+use ts_core::tuple::Tuple;
+
+use super::{Op, Request, Response};
+
+/// How many events a node keeps for resuming subscribers.
+pub const HISTORY_LEN: usize = 10_000;
+
+/// How many events a live subscriber may fall behind before its stream ends.
+const LIVE_CAPACITY: usize = 1024;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum WatchKind {
+    /// The tuple was written, by a `Set`, `SetWithTtl`, `Replace` or transaction.
+    Written,
+    /// The tuple was taken, by a `Get`, `GetAll`, `Replace` or transaction.
+    Taken,
+}
+
+impl WatchKind {
+    /// The SSE event name.
+    pub fn name(self) -> &'static str {
+        match self {
+            WatchKind::Written => "written",
+            WatchKind::Taken => "taken",
+        }
+    }
+}
+
+/// Where an event stands in the log: the index of the entry that caused it and its place among
+/// the events of that entry. Written as `<index>-<ordinal>`, the SSE event id.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct EventPosition {
+    pub index: u64,
+    pub ordinal: u32,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct WatchEvent {
+    pub position: EventPosition,
+    pub kind: WatchKind,
+    pub tuple: Tuple,
+}
+
+/// Recent events and the live subscribers of this node.
+pub struct WatchHub {
+    history: Mutex<History>,
+    live: broadcast::Sender<WatchEvent>,
+}
+
+#[derive(Default)]
+struct History {
+    events: VecDeque<WatchEvent>,
+    /// The last event no longer kept, or that was never seen because a snapshot replaced the
+    /// state machine. No subscriber can resume at or before it.
+    dropped_through: Option<EventPosition>,
+}
+
+impl WatchHub {
+    /// Records the events of applying `req` at `log_index`, which returned `response`.
+    pub(super) fn publish(&self, log_index: u64, req: &Request, response: &Response) {
+        let events = events(req, response);
+        if events.is_empty() {
+            return;
+        }
+        // Sent under the history lock, so a subscriber gets each event either replayed or live.
+        let mut history = self.history.lock().unwrap();
+        for (ordinal, (kind, tuple)) in events.into_iter().enumerate() {
+            let event = WatchEvent {
+                position: EventPosition { index: log_index, ordinal: ordinal as u32 },
+                kind,
+                tuple,
+            };
+            if history.events.len() >= HISTORY_LEN {
+                history.dropped_through = history.events.pop_front().map(|dropped| dropped.position);
+            }
+            history.events.push_back(event.clone());
+            // No subscribers is fine.
+            let _ = self.live.send(event);
+        }
+    }
+
+    /// Forgets every event up to `last_index`, after installing a snapshot that covers them.
+    pub(super) fn skip_through(&self, last_index: u64) {
+        let mut history = self.history.lock().unwrap();
+        history.events.clear();
+        history.dropped_through = Some(EventPosition { index: last_index, ordinal: u32::MAX });
+    }
+
+    /// The kept events from `from` on, followed by a receiver of the events after them. `from` of
+    /// `None` only subscribes to new events. Fails with the earliest position still kept if some
+    /// events from `from` on are gone.
+    pub fn subscribe(&self, from: Option<EventPosition>) -> Result<(Vec<WatchEvent>, broadcast::Receiver<WatchEvent>), EventPosition> {
+        let history = self.history.lock().unwrap();
+        let replay = match from {
+            Some(from) => {
+                if let Some(dropped_through) = history.dropped_through {
+                    if from <= dropped_through {
+                        let earliest = history.events.front().map(|event| event.position);
+                        return Err(earliest.unwrap_or(EventPosition { index: dropped_through.index + 1, ordinal: 0 }));
+                    }
+                }
+                history.events.iter().filter(|event| event.position >= from).cloned().collect()
+            }
+            None => Vec::new(),
+        };
+        Ok((replay, self.live.subscribe()))
+    }
+}
+
+impl Default for WatchHub {
+    fn default() -> Self {
+        WatchHub {
+            history: Mutex::new(History::default()),
+            live: broadcast::channel(LIVE_CAPACITY).0,
+        }
+    }
+}
+
+impl fmt::Debug for WatchHub {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("WatchHub")
+            .field("history_len", &self.history.lock().unwrap().events.len())
+            .field("subscribers", &self.live.receiver_count())
+            .finish()
+    }
+}
+
+impl fmt::Display for EventPosition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}-{}", self.index, self.ordinal)
+    }
+}
+
+impl FromStr for EventPosition {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("invalid event position: {}", s);
+        let (index, ordinal) = s.split_once('-').ok_or_else(invalid)?;
+        Ok(EventPosition {
+            index: index.parse().map_err(|_| invalid())?,
+            ordinal: ordinal.parse().map_err(|_| invalid())?,
+        })
+    }
+}
+
+/// The tuples `req` wrote and took, in the order it did so. A tuple dropped by a `Tick` is
+/// neither; a retried `Session` request did nothing.
+fn events(req: &Request, response: &Response) -> Vec<(WatchKind, Tuple)> {
+    match req {
+        Request::Set { tuple } | Request::SetWithTtl { tuple, .. } => vec![(WatchKind::Written, tuple.clone())],
+        Request::Get { .. } => response.value.iter().map(|tuple| (WatchKind::Taken, tuple.clone())).collect(),
+        Request::GetAll { .. } => response.values.iter().map(|tuple| (WatchKind::Taken, tuple.clone())).collect(),
+        Request::Replace { tuple, .. } => match &response.value {
+            Some(taken) => vec![(WatchKind::Taken, taken.clone()), (WatchKind::Written, tuple.clone())],
+            None => Vec::new(),
+        },
+        Request::Transaction(ops) => {
+            if response.aborted_at.is_some() {
+                return Vec::new();
+            }
+            ops.iter().zip(&response.results).filter_map(|(op, result)| match (op, result) {
+                (Op::Write { tuple }, _) => Some((WatchKind::Written, tuple.clone())),
+                (Op::Take { .. }, Some(taken)) => Some((WatchKind::Taken, taken.clone())),
+                _ => None,
+            }).collect()
+        }
+        Request::Tick { .. } => Vec::new(),
+        Request::Session { request, .. } => {
+            if response.duplicate {
+                Vec::new()
+            } else {
+                events(request, response)
+            }
+        }
+    }
+}
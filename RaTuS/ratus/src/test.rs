@@ -3,7 +3,10 @@ use std::sync::Arc;
 use openraft::testing::StoreBuilder;
 use openraft::testing::Suite;
 use openraft::StorageError;
+use openraft::StorageIOError;
+use tempfile::TempDir;
 
+use crate::store::FileLogStore;
 use crate::store::LogStore;
 use crate::store::StateMachineStore;
 use crate::NodeId;
@@ -17,8 +20,29 @@ impl StoreBuilder<TypeConfig, LogStore, Arc<StateMachineStore>, ()> for MemKVSto
     }
 }
 
+/// Opens each store in a fresh temporary directory, removed when the returned guard drops.
+struct FileStoreBuilder {}
+
+impl StoreBuilder<TypeConfig, FileLogStore, Arc<StateMachineStore>, TempDir> for FileStoreBuilder {
+    async fn build(&self) -> Result<(TempDir, FileLogStore, Arc<StateMachineStore>), StorageError<NodeId>> {
+        let data_dir = TempDir::new().map_err(|e| StorageIOError::write(&e))?;
+        let log_store = FileLogStore::open(data_dir.path()).map_err(|e| StorageIOError::read_logs(&e))?;
+        let state_machine_store =
+            StateMachineStore::open(data_dir.path()).map_err(|e| StorageIOError::read_snapshot(None, &e))?;
+        // Also covers the compressed snapshot encoding.
+        let state_machine_store = state_machine_store.compress_snapshots(true);
+        Ok((data_dir, log_store, Arc::new(state_machine_store)))
+    }
+}
+
 #[test]
 pub fn test_mem_store() -> Result<(), StorageError<NodeId>> {
     Suite::test_all(MemKVStoreBuilder {})?;
     Ok(())
 }
+
+#[test]
+pub fn test_file_store() -> Result<(), StorageError<NodeId>> {
+    Suite::test_all(FileStoreBuilder {})?;
+    Ok(())
+}
@@ -0,0 +1,55 @@
+use std::{sync::Arc, time::Duration};
+
+use ts_core::store::{now_millis, Store};
+
+use system::Logger;
+
+use crate::{store::Request, NodeId, Raft, StateMachineStore};
+
+/// How late past its TTL a tuple may linger: the longest the leader sleeps before checking for
+/// due tuples, and how far the state machine clock may lag before a `Request::SetWithTtl`.
+pub const TICK_INTERVAL: Duration = Duration::from_millis(1_000);
+
+/// Spawns the task that drives tuple expiry: while this node is the leader it proposes its
+/// wall clock as a `Request::Tick` whenever a stored tuple is due, sleeping until the earliest
+/// expiry, but at most `interval`, in between. Followers never expire tuples on their own; they
+/// apply the same ticks from the log.
+pub fn spawn_ticker(raft: Raft, node_id: NodeId, state_machine_store: Arc<StateMachineStore>, interval: Duration) -> tokio::task::JoinHandle<()> {
+    tokio::spawn(async move {
+        loop {
+            let is_leader = raft.metrics().borrow().current_leader == Some(node_id);
+            let next_expiry = if is_leader {
+                state_machine_store.state_machine.read().await.data.next_expiry().unwrap_or_default()
+            } else {
+                None
+            };
+            let now = now_millis();
+            match next_expiry {
+                Some(expires_at) if expires_at <= now => {
+                    if let Err(error) = raft.client_write(Request::Tick { now }).await {
+                        Logger::error(format!("tick not committed: {:?}", error), true);
+                        tokio::time::sleep(interval).await;
+                    }
+                }
+                // Woken up before the expiry if need be, for a tuple written meanwhile that
+                // expires earlier.
+                Some(expires_at) => tokio::time::sleep(Duration::from_millis(expires_at - now).min(interval)).await,
+                None => tokio::time::sleep(interval).await,
+            }
+        }
+    })
+}
+
+/// Proposes the wall clock as a `Request::Tick` if the state machine clock lags it by more than
+/// `interval`, as it does after a while without expiries. Called on the leader before a request
+/// whose TTL counts from that clock.
+pub async fn refresh_clock(raft: &Raft, state_machine_store: &StateMachineStore, interval: Duration) {
+    let clock = state_machine_store.state_machine.read().await.clock;
+    let now = now_millis();
+    if now.saturating_sub(clock) <= interval.as_millis() as u64 {
+        return;
+    }
+    if let Err(error) = raft.client_write(Request::Tick { now }).await {
+        Logger::error(format!("tick not committed: {:?}", error), true);
+    }
+}
@@ -1,6 +1,7 @@
 use crate::error::Error;
 use crate::result::Result;
 use reqwest::{StatusCode, Url};
+use std::time::Duration;
 use ts_core::query_tuple::QueryTuple;
 use ts_core::tuple::Tuple;
 
@@ -9,6 +10,10 @@ pub struct Client {
     write_url: Url,
     read_url: Url,
     get_url: Url,
+    read_all_url: Url,
+    get_all_url: Url,
+    count_url: Url,
+    replace_url: Url,
     http_client: reqwest::Client,
 }
 
@@ -43,6 +48,24 @@ impl Client {
         }
     }
 
+    /// Like [`Client::write`], but the server drops the tuple once `ttl` has passed.
+    pub async fn write_with_ttl(&self, tuple: &Tuple, ttl: Duration) -> Result<()> {
+        let mut url = self.write_url.clone();
+        url.query_pairs_mut()
+            .append_pair("ttl", &format!("{}ms", ttl.as_millis()));
+        let response = self
+            .http_client
+            .post(url)
+            .body(serde_json::to_string(tuple)?)
+            .send()
+            .await?;
+
+        match response.status() {
+            StatusCode::CREATED => Ok(()),
+            _ => Err(Error::ServerError),
+        }
+    }
+
     pub async fn read(&self, tuple: &QueryTuple) -> Result<Option<Tuple>> {
         let response = self
             .http_client
@@ -72,6 +95,107 @@ impl Client {
             _ => Err(Error::ServerError),
         }
     }
+
+    /// Like [`Client::read`], but the server holds the request open for up to `wait` until a
+    /// matching tuple is written.
+    pub async fn read_wait(&self, tuple: &QueryTuple, wait: Duration) -> Result<Option<Tuple>> {
+        self.wait_for(&self.read_url, tuple, wait).await
+    }
+
+    /// Like [`Client::get`], but the server holds the request open for up to `wait` until a
+    /// matching tuple is written.
+    pub async fn get_wait(&self, tuple: &QueryTuple, wait: Duration) -> Result<Option<Tuple>> {
+        self.wait_for(&self.get_url, tuple, wait).await
+    }
+
+    /// Copies of all tuples matching `tuple`, at most `limit` of them if given.
+    pub async fn read_all(&self, tuple: &QueryTuple, limit: Option<usize>) -> Result<Vec<Tuple>> {
+        self.bulk(&self.read_all_url, tuple, limit).await
+    }
+
+    /// Removes all tuples matching `tuple` in a single request, at most `limit` of them if given.
+    pub async fn get_all(&self, tuple: &QueryTuple, limit: Option<usize>) -> Result<Vec<Tuple>> {
+        self.bulk(&self.get_all_url, tuple, limit).await
+    }
+
+    pub async fn count(&self, tuple: &QueryTuple) -> Result<usize> {
+        let response = self
+            .http_client
+            .post(self.count_url.clone())
+            .body(serde_json::to_string(tuple)?)
+            .send()
+            .await?;
+
+        match response.status() {
+            StatusCode::OK => Ok(response.json::<usize>().await?),
+            _ => Err(Error::ServerError),
+        }
+    }
+
+    /// Atomically takes one tuple matching `query_tuple` and writes `tuple` in its place.
+    ///
+    /// Returns the taken tuple, or `None` if nothing matched and nothing was written.
+    pub async fn replace(&self, query_tuple: &QueryTuple, tuple: &Tuple) -> Result<Option<Tuple>> {
+        let response = self
+            .http_client
+            .post(self.replace_url.clone())
+            .body(serde_json::to_string(&(query_tuple, tuple))?)
+            .send()
+            .await?;
+
+        match response.status() {
+            StatusCode::OK => Ok(Some(response.json::<Tuple>().await?)),
+            StatusCode::NOT_FOUND => Ok(None),
+            _ => Err(Error::ServerError),
+        }
+    }
+
+    async fn bulk(
+        &self,
+        url: &Url,
+        tuple: &QueryTuple,
+        limit: Option<usize>,
+    ) -> Result<Vec<Tuple>> {
+        let mut url = url.clone();
+        if let Some(limit) = limit {
+            url.query_pairs_mut()
+                .append_pair("limit", &limit.to_string());
+        }
+        let response = self
+            .http_client
+            .post(url)
+            .body(serde_json::to_string(tuple)?)
+            .send()
+            .await?;
+
+        match response.status() {
+            StatusCode::OK => Ok(response.json::<Vec<Tuple>>().await?),
+            _ => Err(Error::ServerError),
+        }
+    }
+
+    async fn wait_for(
+        &self,
+        url: &Url,
+        tuple: &QueryTuple,
+        wait: Duration,
+    ) -> Result<Option<Tuple>> {
+        let mut url = url.clone();
+        url.query_pairs_mut()
+            .append_pair("wait", &format!("{}ms", wait.as_millis()));
+        let response = self
+            .http_client
+            .post(url)
+            .body(serde_json::to_string(tuple)?)
+            .send()
+            .await?;
+
+        match response.status() {
+            StatusCode::OK => Ok(Some(response.json::<Tuple>().await?)),
+            StatusCode::NOT_FOUND => Ok(None),
+            _ => Err(Error::ServerError),
+        }
+    }
 }
 
 impl Builder {
@@ -81,6 +205,10 @@ impl Builder {
         let read_url = base_server.join("read")?;
         let get_url = base_server.join("get")?;
         let write_url = base_server.join("write")?;
+        let read_all_url = base_server.join("read-all")?;
+        let get_all_url = base_server.join("get-all")?;
+        let count_url = base_server.join("count")?;
+        let replace_url = base_server.join("replace")?;
 
         Ok(Client {
             http_client: reqwest::Client::new(),
@@ -88,6 +216,10 @@ impl Builder {
             read_url,
             get_url,
             write_url,
+            read_all_url,
+            get_all_url,
+            count_url,
+            replace_url,
         })
     }
 }
@@ -7,6 +7,7 @@ pub enum Error {
     MutexPoisonError,
     NonConcreteTuple(Tuple),
     NotFound,
+    InvalidPattern(String),
 }
 
 impl<S> From<std::sync::PoisonError<std::sync::MutexGuard<'_, S>>> for Error
@@ -17,3 +18,18 @@ where
         Error::MutexPoisonError
     }
 }
+
+impl<S> From<std::sync::PoisonError<(std::sync::MutexGuard<'_, S>, std::sync::WaitTimeoutResult)>>
+    for Error
+where
+    S: Store,
+{
+    fn from(
+        _error: std::sync::PoisonError<(
+            std::sync::MutexGuard<'_, S>,
+            std::sync::WaitTimeoutResult,
+        )>,
+    ) -> Error {
+        Error::MutexPoisonError
+    }
+}
@@ -0,0 +1,610 @@
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+use crate::policy::{MatchPolicy, Selector};
+use crate::query_tuple::QueryTuple;
+use crate::result::Result;
+use crate::store::{Rollback, Store};
+use crate::tuple::Tuple;
+use crate::types::Types;
+
+/// Hashable stand-in for a field value, floats keyed by their bit pattern.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum IndexKey {
+    Boolean(bool),
+    Integer(i64),
+    Float(u64),
+    String(String),
+}
+
+impl From<&Types> for IndexKey {
+    fn from(value: &Types) -> Self {
+        match value {
+            Types::Boolean(boolean) => IndexKey::Boolean(*boolean),
+            Types::Integer(integer) => IndexKey::Integer(*integer),
+            // `0.0 == -0.0`, so both must land on the same key.
+            Types::Float(float) if *float == 0.0 => IndexKey::Float(0),
+            Types::Float(float) => IndexKey::Float(float.to_bits()),
+            Types::String(string) => IndexKey::String(string.clone()),
+        }
+    }
+}
+
+/// All tuples of one arity, indexed by their first field.
+#[derive(Debug, Clone, Default)]
+struct Bucket {
+    /// Tuples by insertion sequence number, so iteration is oldest first.
+    tuples: BTreeMap<u64, Tuple>,
+    by_first_field: HashMap<IndexKey, BTreeSet<u64>>,
+}
+
+impl Bucket {
+    fn insert(&mut self, sequence: u64, tuple: Tuple) {
+        if !tuple.is_empty() {
+            self.by_first_field
+                .entry(IndexKey::from(&tuple[0]))
+                .or_default()
+                .insert(sequence);
+        }
+        self.tuples.insert(sequence, tuple);
+    }
+
+    fn remove(&mut self, sequence: u64) -> Option<Tuple> {
+        let tuple = self.tuples.remove(&sequence)?;
+        if !tuple.is_empty() {
+            let key = IndexKey::from(&tuple[0]);
+            if let Some(sequences) = self.by_first_field.get_mut(&key) {
+                sequences.remove(&sequence);
+                if sequences.is_empty() {
+                    self.by_first_field.remove(&key);
+                }
+            }
+        }
+        Some(tuple)
+    }
+
+    /// Sequence numbers of the matching tuples, oldest or newest first, stopping after `limit`
+    /// of them. Only the tuples sharing the query's first field are visited when that field is
+    /// an exact match.
+    fn matches(
+        &self,
+        query_tuple: &QueryTuple,
+        newest_first: bool,
+        limit: Option<usize>,
+    ) -> Vec<u64> {
+        let first_field = if query_tuple.is_empty() {
+            None
+        } else {
+            query_tuple[0].exact_value()
+        };
+        let candidates: Box<dyn DoubleEndedIterator<Item = &u64>> = match first_field {
+            Some(first_field) => match self.by_first_field.get(&IndexKey::from(&first_field)) {
+                Some(sequences) => Box::new(sequences.iter()),
+                None => return Vec::new(),
+            },
+            None => Box::new(self.tuples.keys()),
+        };
+        let matching = |sequence: &&u64| query_tuple == &self.tuples[*sequence];
+        let limit = limit.unwrap_or(usize::MAX);
+        if newest_first {
+            candidates
+                .rev()
+                .filter(matching)
+                .take(limit)
+                .copied()
+                .collect()
+        } else {
+            candidates.filter(matching).take(limit).copied().collect()
+        }
+    }
+}
+
+/// A [`Store`] that buckets tuples by arity and indexes each bucket by the tuples' first field,
+/// so a query only scans the tuples it could possibly match instead of the whole store.
+///
+/// Matching follows the store's [`MatchPolicy`], oldest first by default, like
+/// [`VecStore`](crate::vec_store::VecStore). Serialized as the tuples in write order plus the
+/// policy state and expiry times.
+#[derive(Clone, Serialize, Deserialize, Debug, Default)]
+#[serde(from = "StoredTuples", into = "StoredTuples")]
+pub struct IndexedStore {
+    buckets: HashMap<usize, Bucket>,
+    next_sequence: u64,
+    tuple_count: usize,
+    selector: Selector,
+    /// Expiry times by sequence number, for tuples written with one.
+    expiries: HashMap<u64, u64>,
+    /// `(expires_at, arity, sequence)` in expiry order, so a sweep only visits due tuples.
+    deadlines: BTreeSet<(u64, usize, u64)>,
+    /// The changes since [`Rollback::begin`], while one is open.
+    journal: Option<Journal>,
+}
+
+/// What [`Rollback::rollback`] needs to put an [`IndexedStore`] back as it was.
+#[derive(Clone, Debug)]
+struct Journal {
+    selector: Selector,
+    next_sequence: u64,
+    changes: Vec<Change>,
+}
+
+#[derive(Clone, Debug)]
+enum Change {
+    Inserted((usize, u64)),
+    Removed {
+        sequence: u64,
+        tuple: Tuple,
+        expires_at: Option<u64>,
+    },
+}
+
+#[derive(Serialize, Deserialize)]
+struct StoredTuples {
+    #[serde(default)]
+    selector: Selector,
+    tuples: Vec<Tuple>,
+    /// `(index in tuples, expires_at)` pairs.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    expiries: Vec<(usize, u64)>,
+}
+
+/// The contents of an [`IndexedStore`] in plain form, for encodings other than serde's: the
+/// tuples in write order with their expiry times, and the match policy with its generator state.
+/// Converting back yields a store that makes the same picks.
+#[derive(Debug, Clone, PartialEq)]
+pub struct StoreImage {
+    pub policy: MatchPolicy,
+    pub generator_state: u64,
+    pub entries: Vec<(Tuple, Option<u64>)>,
+}
+
+impl IndexedStore {
+    pub fn with_policy(policy: MatchPolicy) -> Self {
+        IndexedStore {
+            selector: Selector::new(policy),
+            ..Default::default()
+        }
+    }
+
+    pub fn policy(&self) -> MatchPolicy {
+        self.selector.policy()
+    }
+
+    /// Matching tuples as `(arity, sequence)` pairs, at most `limit` of them, in the order given
+    /// by the store's policy. `selector` is the store's own, or a copy of it for reads.
+    ///
+    /// Oldest and newest first walk each bucket in sequence order and stop at `limit`; only a
+    /// random pick needs every match.
+    fn locate(
+        &self,
+        selector: &mut Selector,
+        query_tuple: &QueryTuple,
+        limit: Option<usize>,
+    ) -> Vec<(usize, u64)> {
+        let (newest_first, bucket_limit) = match selector.policy() {
+            MatchPolicy::OldestFirst => (false, limit),
+            MatchPolicy::NewestFirst => (true, limit),
+            MatchPolicy::Random { .. } => (false, None),
+        };
+        let mut located: Vec<(usize, u64)> = match query_tuple.arity() {
+            (min, Some(max)) if min == max => match self.buckets.get(&min) {
+                Some(bucket) => bucket
+                    .matches(query_tuple, newest_first, bucket_limit)
+                    .into_iter()
+                    .map(|sequence| (min, sequence))
+                    .collect(),
+                None => Vec::new(),
+            },
+            (min, max) => {
+                let mut located: Vec<(usize, u64)> = self
+                    .buckets
+                    .iter()
+                    .filter(|(arity, _)| {
+                        **arity >= min && !matches!(max, Some(max) if **arity > max)
+                    })
+                    .flat_map(|(arity, bucket)| {
+                        bucket
+                            .matches(query_tuple, newest_first, bucket_limit)
+                            .into_iter()
+                            .map(move |sequence| (*arity, sequence))
+                    })
+                    .collect();
+                located.sort_by_key(|(_, sequence)| *sequence);
+                if newest_first {
+                    located.reverse();
+                }
+                located
+            }
+        };
+        match selector.policy() {
+            MatchPolicy::Random { .. } => selector.select(located, limit),
+            _ => {
+                located.truncate(limit.unwrap_or(usize::MAX));
+                located
+            }
+        }
+    }
+
+    /// [`IndexedStore::locate`] with the store's own selector, for operations that take tuples.
+    fn locate_mut(&mut self, query_tuple: &QueryTuple, limit: Option<usize>) -> Vec<(usize, u64)> {
+        let mut selector = self.selector.clone();
+        let located = self.locate(&mut selector, query_tuple, limit);
+        self.selector = selector;
+        located
+    }
+
+    fn tuple(&self, (arity, sequence): (usize, u64)) -> Option<Tuple> {
+        self.buckets[&arity].tuples.get(&sequence).cloned()
+    }
+
+    fn insert(&mut self, tuple: &Tuple, expires_at: Option<u64>) {
+        let sequence = self.next_sequence;
+        self.next_sequence += 1;
+        self.insert_at(sequence, tuple.clone(), expires_at);
+        if let Some(journal) = self.journal.as_mut() {
+            journal
+                .changes
+                .push(Change::Inserted((tuple.len(), sequence)));
+        }
+    }
+
+    fn insert_at(&mut self, sequence: u64, tuple: Tuple, expires_at: Option<u64>) {
+        let arity = tuple.len();
+        self.buckets
+            .entry(arity)
+            .or_default()
+            .insert(sequence, tuple);
+        if let Some(expires_at) = expires_at {
+            self.expiries.insert(sequence, expires_at);
+            self.deadlines.insert((expires_at, arity, sequence));
+        }
+        self.tuple_count += 1;
+    }
+
+    fn remove(&mut self, (arity, sequence): (usize, u64)) -> Option<Tuple> {
+        let bucket = self.buckets.get_mut(&arity)?;
+        let tuple = bucket.remove(sequence)?;
+        if bucket.tuples.is_empty() {
+            self.buckets.remove(&arity);
+        }
+        let expires_at = self.expiries.remove(&sequence);
+        if let Some(expires_at) = expires_at {
+            self.deadlines.remove(&(expires_at, arity, sequence));
+        }
+        self.tuple_count -= 1;
+        if let Some(journal) = self.journal.as_mut() {
+            journal.changes.push(Change::Removed {
+                sequence,
+                tuple: tuple.clone(),
+                expires_at,
+            });
+        }
+        Some(tuple)
+    }
+}
+
+impl Rollback for IndexedStore {
+    fn begin(&mut self) {
+        self.journal = Some(Journal {
+            selector: self.selector.clone(),
+            next_sequence: self.next_sequence,
+            changes: Vec::new(),
+        });
+    }
+
+    fn commit(&mut self) {
+        self.journal = None;
+    }
+
+    fn rollback(&mut self) {
+        let Some(journal) = self.journal.take() else {
+            return;
+        };
+        for change in journal.changes.into_iter().rev() {
+            match change {
+                Change::Inserted(location) => {
+                    self.remove(location);
+                }
+                Change::Removed {
+                    sequence,
+                    tuple,
+                    expires_at,
+                } => self.insert_at(sequence, tuple, expires_at),
+            }
+        }
+        self.selector = journal.selector;
+        self.next_sequence = journal.next_sequence;
+    }
+}
+
+impl Store for IndexedStore {
+    fn size(&self) -> Result<usize> {
+        Ok(self.tuple_count)
+    }
+
+    fn write(&mut self, tuple: &Tuple) -> Result<()> {
+        self.insert(tuple, None);
+        Ok(())
+    }
+
+    fn write_with_expiry(&mut self, tuple: &Tuple, expires_at: u64) -> Result<()> {
+        self.insert(tuple, Some(expires_at));
+        Ok(())
+    }
+
+    fn expire(&mut self, now: u64) -> Result<usize> {
+        let due: Vec<(u64, usize, u64)> = self
+            .deadlines
+            .range(..=(now, usize::MAX, u64::MAX))
+            .copied()
+            .collect();
+        for (_, arity, sequence) in due.iter() {
+            self.remove((*arity, *sequence));
+        }
+        Ok(due.len())
+    }
+
+    fn next_expiry(&self) -> Result<Option<u64>> {
+        Ok(self.deadlines.first().map(|(expires_at, _, _)| *expires_at))
+    }
+
+    fn read(&self, query_tuple: &QueryTuple) -> Result<Option<Tuple>> {
+        Ok(self
+            .locate(&mut self.selector.clone(), query_tuple, Some(1))
+            .first()
+            .and_then(|location| self.tuple(*location)))
+    }
+
+    fn get(&mut self, query_tuple: &QueryTuple) -> Result<Option<Tuple>> {
+        Ok(match self.locate_mut(query_tuple, Some(1)).first() {
+            Some(location) => self.remove(*location),
+            None => None,
+        })
+    }
+
+    fn read_all(&self, query_tuple: &QueryTuple, limit: Option<usize>) -> Result<Vec<Tuple>> {
+        Ok(self
+            .locate(&mut self.selector.clone(), query_tuple, limit)
+            .into_iter()
+            .filter_map(|location| self.tuple(location))
+            .collect())
+    }
+
+    fn get_all(&mut self, query_tuple: &QueryTuple, limit: Option<usize>) -> Result<Vec<Tuple>> {
+        Ok(self
+            .locate_mut(query_tuple, limit)
+            .into_iter()
+            .filter_map(|location| self.remove(location))
+            .collect())
+    }
+
+    fn count(&self, query_tuple: &QueryTuple) -> Result<usize> {
+        Ok(self
+            .locate(&mut Selector::default(), query_tuple, None)
+            .len())
+    }
+}
+
+impl From<Vec<Tuple>> for IndexedStore {
+    fn from(tuples: Vec<Tuple>) -> Self {
+        IndexedStore::from(StoredTuples {
+            selector: Selector::default(),
+            tuples,
+            expiries: Vec::new(),
+        })
+    }
+}
+
+impl From<StoredTuples> for IndexedStore {
+    fn from(stored: StoredTuples) -> Self {
+        let mut store = IndexedStore {
+            selector: stored.selector,
+            ..Default::default()
+        };
+        let expiries: HashMap<usize, u64> = stored.expiries.into_iter().collect();
+        for (index, tuple) in stored.tuples.iter().enumerate() {
+            store.insert(tuple, expiries.get(&index).copied());
+        }
+        store
+    }
+}
+
+impl From<IndexedStore> for Vec<Tuple> {
+    fn from(store: IndexedStore) -> Self {
+        StoredTuples::from(store).tuples
+    }
+}
+
+impl From<IndexedStore> for StoredTuples {
+    fn from(store: IndexedStore) -> Self {
+        let IndexedStore {
+            buckets,
+            selector,
+            expiries,
+            ..
+        } = store;
+        let mut tuples: Vec<(u64, Tuple)> = buckets
+            .into_values()
+            .flat_map(|bucket| bucket.tuples.into_iter())
+            .collect();
+        tuples.sort_by_key(|(sequence, _)| *sequence);
+        let expiries = tuples
+            .iter()
+            .enumerate()
+            .filter_map(|(index, (sequence, _))| {
+                expiries
+                    .get(sequence)
+                    .map(|expires_at| (index, *expires_at))
+            })
+            .collect();
+        StoredTuples {
+            selector,
+            tuples: tuples.into_iter().map(|(_, tuple)| tuple).collect(),
+            expiries,
+        }
+    }
+}
+
+impl From<IndexedStore> for StoreImage {
+    fn from(store: IndexedStore) -> Self {
+        let stored = StoredTuples::from(store);
+        let expiries: HashMap<usize, u64> = stored.expiries.into_iter().collect();
+        let entries = stored
+            .tuples
+            .into_iter()
+            .enumerate()
+            .map(|(index, tuple)| (tuple, expiries.get(&index).copied()))
+            .collect();
+        StoreImage {
+            policy: stored.selector.policy(),
+            generator_state: stored.selector.state(),
+            entries,
+        }
+    }
+}
+
+impl From<StoreImage> for IndexedStore {
+    fn from(image: StoreImage) -> Self {
+        let mut store = IndexedStore {
+            selector: Selector::resume(image.policy, image.generator_state),
+            ..Default::default()
+        };
+        for (tuple, expires_at) in image.entries.iter() {
+            store.insert(tuple, *expires_at);
+        }
+        store
+    }
+}
+
+#[test]
+fn test_indexed_store() -> Result<()> {
+    let mut tuple_store = IndexedStore::default();
+
+    tuple_store.write(&Tuple::builder().integer(5).build())?;
+    tuple_store.write(&Tuple::builder().integer(2).build())?;
+    tuple_store.write(&Tuple::builder().string("job").integer(1).build())?;
+    tuple_store.write(&Tuple::builder().string("job").integer(2).float(0.5).build())?;
+
+    assert_eq!(4, tuple_store.size()?);
+
+    match tuple_store.read(&QueryTuple::builder().integer(2).build())? {
+        Some(_tuple) => (),
+        None => panic!("No tuple found"),
+    }
+    assert_eq!(4, tuple_store.size()?);
+
+    match tuple_store.get(&QueryTuple::builder().any_integer().build())? {
+        Some(tuple) => assert_eq!(QueryTuple::builder().integer(5).build(), tuple),
+        None => panic!("No tuple found"),
+    }
+    assert_eq!(3, tuple_store.size()?);
+
+    let prefix_query_tuple = QueryTuple::builder().string("job").rest().build();
+    assert_eq!(2, tuple_store.count(&prefix_query_tuple)?);
+    match tuple_store.read(&prefix_query_tuple)? {
+        Some(tuple) => assert_eq!(QueryTuple::builder().any().integer(1).build(), tuple),
+        None => panic!("No tuple found"),
+    }
+
+    let any_query_tuple = QueryTuple::builder().rest().build();
+    let tuples: Vec<Tuple> = tuple_store.clone().into();
+    assert_eq!(tuple_store.read_all(&any_query_tuple, None)?, tuples);
+
+    assert_eq!(2, tuple_store.get_all(&prefix_query_tuple, None)?.len());
+    assert_eq!(1, tuple_store.size()?);
+    assert!(tuple_store
+        .get(&QueryTuple::builder().string("job").any_integer().build())?
+        .is_none());
+
+    Ok(())
+}
+
+#[test]
+fn test_indexed_store_policy() -> Result<()> {
+    let mut tuple_store = IndexedStore::with_policy(MatchPolicy::NewestFirst);
+
+    tuple_store.write(&Tuple::builder().string("job").integer(1).build())?;
+    tuple_store.write(&Tuple::builder().string("job").integer(2).build())?;
+    tuple_store.write(&Tuple::builder().string("job").integer(3).float(0.5).build())?;
+
+    let newest_two =
+        tuple_store.read_all(&QueryTuple::builder().string("job").rest().build(), Some(2))?;
+    assert_eq!(
+        vec![
+            Tuple::builder().string("job").integer(3).float(0.5).build(),
+            Tuple::builder().string("job").integer(2).build(),
+        ],
+        newest_two
+    );
+
+    match tuple_store.get(&QueryTuple::builder().string("job").any_integer().build())? {
+        Some(tuple) => assert_eq!(QueryTuple::builder().any().integer(2).build(), tuple),
+        None => panic!("No tuple found"),
+    }
+    match tuple_store.get(&QueryTuple::builder().string("job").rest().build())? {
+        Some(tuple) => assert_eq!(QueryTuple::builder().any().integer(3).any().build(), tuple),
+        None => panic!("No tuple found"),
+    }
+
+    let stored: StoredTuples = tuple_store.clone().into();
+    assert_eq!(
+        MatchPolicy::NewestFirst,
+        IndexedStore::from(stored).policy()
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_indexed_store_expiry() -> Result<()> {
+    let mut tuple_store = IndexedStore::default();
+
+    tuple_store.write_with_expiry(&Tuple::builder().string("lock").integer(1).build(), 100)?;
+    tuple_store.write_with_expiry(&Tuple::builder().string("heartbeat").build(), 200)?;
+    tuple_store.write(&Tuple::builder().string("job").integer(1).build())?;
+
+    let lock_query_tuple = QueryTuple::builder().string("lock").any_integer().build();
+    assert_eq!(Some(100), tuple_store.next_expiry()?);
+    assert_eq!(0, tuple_store.expire(99)?);
+    assert!(tuple_store.read(&lock_query_tuple)?.is_some());
+    assert_eq!(1, tuple_store.expire(100)?);
+    assert!(tuple_store.read(&lock_query_tuple)?.is_none());
+    assert_eq!(Some(200), tuple_store.next_expiry()?);
+
+    let restored = IndexedStore::from(StoredTuples::from(tuple_store.clone()));
+    assert_eq!(2, restored.size()?);
+
+    // A taken tuple no longer expires.
+    let mut taken_store = restored.clone();
+    assert!(taken_store
+        .get(&QueryTuple::builder().string("heartbeat").build())?
+        .is_some());
+    assert_eq!(None, taken_store.next_expiry()?);
+    assert_eq!(0, taken_store.expire(u64::MAX)?);
+
+    let mut restored = restored;
+    assert_eq!(1, restored.expire(200)?);
+    assert_eq!(1, restored.size()?);
+
+    Ok(())
+}
+
+#[test]
+fn test_store_image() -> Result<()> {
+    let mut tuple_store = IndexedStore::with_policy(MatchPolicy::Random { seed: 3 });
+
+    tuple_store.write(&Tuple::builder().string("job").integer(1).build())?;
+    tuple_store.write_with_expiry(&Tuple::builder().string("job").integer(2).build(), 100)?;
+    tuple_store.write(&Tuple::builder().string("job").integer(3).build())?;
+    tuple_store.get(&QueryTuple::builder().string("job").any_integer().build())?;
+
+    let image = StoreImage::from(tuple_store.clone());
+    assert_eq!(2, image.entries.len());
+    let mut restored = IndexedStore::from(image.clone());
+    assert_eq!(image, StoreImage::from(restored.clone()));
+
+    let query_tuple = QueryTuple::builder().string("job").any_integer().build();
+    assert_eq!(tuple_store.get(&query_tuple)?, restored.get(&query_tuple)?);
+
+    Ok(())
+}
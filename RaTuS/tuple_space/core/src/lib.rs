@@ -1,5 +1,9 @@
 pub mod error;
+pub mod indexed_store;
 pub mod mutex_store;
+pub mod observer;
+pub mod pattern;
+pub mod policy;
 pub mod query_tuple;
 pub mod result;
 pub mod store;
@@ -1,23 +1,189 @@
 use serde::{Deserialize, Serialize};
 
+use crate::error::Error;
+use crate::observer::{self, ObserverId, Observers, StoreEvent};
 use crate::query_tuple::QueryTuple;
 use crate::result::Result;
-use crate::store::Store;
+use crate::store::{now_millis, Rollback, Store};
 use crate::tuple::Tuple;
-use std::sync::{Arc, Mutex};
+use std::sync::{mpsc, Arc, Condvar, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
 
 #[derive(Clone, Serialize, Deserialize, Debug)]
 pub struct MutexStore<S: Store> {
     store: Arc<Mutex<S>>,
+    /// Signalled on every write, so blocking `read`/`get` callers can retry their query.
+    #[serde(skip)]
+    written: Arc<Condvar>,
+    /// Told of every tuple written or taken through any handle.
+    #[serde(skip)]
+    observers: Arc<Mutex<Observers>>,
 }
 
-impl<S: Store> MutexStore<S> {}
+impl<S: Store> MutexStore<S> {
+    /// Shares an already configured store, e.g. one built with a non-default match policy.
+    pub fn new(store: S) -> Self {
+        MutexStore {
+            store: Arc::new(Mutex::new(store)),
+            written: Arc::new(Condvar::new()),
+            observers: Arc::default(),
+        }
+    }
+
+    /// Calls `callback` with every tuple matching `query_tuple` that is written to or taken from
+    /// the store through any handle, until [`MutexStore::unobserve`].
+    ///
+    /// The callback runs on the thread that wrote or took the tuple, after the store is unlocked,
+    /// so it may use the store. Events from one thread arrive in order; events from different
+    /// threads may not arrive in the order the store saw them. `read`s are not reported, nor are
+    /// tuples dropped by `expire`.
+    pub fn observe<F>(&self, query_tuple: QueryTuple, callback: F) -> Result<ObserverId>
+    where
+        F: Fn(&StoreEvent) + Send + Sync + 'static,
+    {
+        let mut observers = self.observers.lock().map_err(|_| Error::MutexPoisonError)?;
+        Ok(observers.add(
+            query_tuple,
+            Arc::new(move |event: &StoreEvent| {
+                callback(event);
+                true
+            }),
+        ))
+    }
+
+    /// Like [`MutexStore::observe`], but sends the events to the returned channel. The observer
+    /// is dropped once the receiver is.
+    pub fn subscribe(
+        &self,
+        query_tuple: QueryTuple,
+    ) -> Result<(ObserverId, mpsc::Receiver<StoreEvent>)> {
+        let mut observers = self.observers.lock().map_err(|_| Error::MutexPoisonError)?;
+        Ok(observers.add_channel(query_tuple))
+    }
+
+    /// Removes an observer, returning whether it was still registered.
+    pub fn unobserve(&self, id: ObserverId) -> Result<bool> {
+        let mut observers = self.observers.lock().map_err(|_| Error::MutexPoisonError)?;
+        Ok(observers.remove(id))
+    }
+
+    fn notify(&self, events: &[StoreEvent]) -> Result<()> {
+        observer::notify(&self.observers, events)
+    }
+
+    /// A copy of the wrapped store as it is now.
+    pub fn to_inner(&self) -> Result<S>
+    where
+        S: Clone,
+    {
+        Ok(self.store.lock()?.clone())
+    }
+
+    /// Runs `operation` on the wrapped store under a single lock, all or nothing: if it returns
+    /// `Err`, the changes it made are rolled back, so the store is exactly as it was, write order,
+    /// expiry times and match policy state included.
+    ///
+    /// Blocked `read_blocking`/`get_blocking` callers are woken once `operation` succeeds.
+    /// Observers are not told of the changes it makes.
+    pub fn atomically<T, E, F>(&self, operation: F) -> Result<std::result::Result<T, E>>
+    where
+        S: Rollback,
+        F: FnOnce(&mut S) -> std::result::Result<T, E>,
+    {
+        let mut guard = self.store.lock()?;
+        guard.begin();
+        let outcome = operation(&mut guard);
+        match outcome {
+            Ok(_) => {
+                guard.commit();
+                self.written.notify_all();
+            }
+            Err(_) => guard.rollback(),
+        }
+        Ok(outcome)
+    }
+
+    /// Blocking `rd`: waits until a tuple matching `query_tuple` is available and returns a
+    /// copy of it, leaving it in the store.
+    ///
+    /// Returns `Ok(None)` only if `timeout` is given and it elapses before a match is written.
+    pub fn read_blocking(
+        &self,
+        query_tuple: &QueryTuple,
+        timeout: Option<Duration>,
+    ) -> Result<Option<Tuple>> {
+        self.wait_for(timeout, |store| store.read(query_tuple))
+    }
+
+    /// Blocking `in`: waits until a tuple matching `query_tuple` is available and removes it
+    /// from the store.
+    ///
+    /// Returns `Ok(None)` only if `timeout` is given and it elapses before a match is written.
+    pub fn get_blocking(
+        &mut self,
+        query_tuple: &QueryTuple,
+        timeout: Option<Duration>,
+    ) -> Result<Option<Tuple>> {
+        let taken = self.wait_for(timeout, |store| store.get(query_tuple))?;
+        if let Some(tuple) = &taken {
+            self.notify(&[StoreEvent::Taken(tuple.clone())])?;
+        }
+        Ok(taken)
+    }
+
+    /// Runs `operation` against the locked store, parking on `written` until it yields a tuple
+    /// or the deadline passes.
+    fn wait_for<F>(&self, timeout: Option<Duration>, mut operation: F) -> Result<Option<Tuple>>
+    where
+        F: FnMut(&mut S) -> Result<Option<Tuple>>,
+    {
+        let deadline = timeout.map(|timeout| Instant::now() + timeout);
+        let mut guard = self.store.lock()?;
+        loop {
+            if let Some(tuple) = operation(&mut guard)? {
+                return Ok(Some(tuple));
+            }
+            guard = match deadline {
+                Some(deadline) => {
+                    let now = Instant::now();
+                    if now >= deadline {
+                        return Ok(None);
+                    }
+                    self.written.wait_timeout(guard, deadline - now)?.0
+                }
+                None => self.written.wait(guard)?,
+            };
+        }
+    }
+}
+
+impl<S: Store + Send + 'static> MutexStore<S> {
+    /// Spawns a thread that drops expired tuples every `interval`, on the local clock.
+    ///
+    /// The thread only holds the store weakly and exits once every handle to it is dropped.
+    pub fn spawn_sweeper(&self, interval: Duration) -> JoinHandle<()> {
+        let store = Arc::downgrade(&self.store);
+        thread::spawn(move || loop {
+            thread::sleep(interval);
+            let store = match store.upgrade() {
+                Some(store) => store,
+                None => break,
+            };
+            let expired = match store.lock() {
+                Ok(mut guard) => guard.expire(now_millis()),
+                Err(_) => break,
+            };
+            if expired.is_err() {
+                break;
+            }
+        })
+    }
+}
 
 impl<S: Store> Default for MutexStore<S> {
     fn default() -> MutexStore<S> {
-        MutexStore {
-            store: Arc::new(Mutex::new(S::default())),
-        }
+        MutexStore::new(S::default())
     }
 }
 
@@ -27,7 +193,9 @@ impl<S: Store> Store for MutexStore<S> {
     }
 
     fn write(&mut self, tuple: &Tuple) -> Result<()> {
-        Ok(self.store.lock()?.write(tuple)?)
+        self.store.lock()?.write(tuple)?;
+        self.written.notify_all();
+        self.notify(&[StoreEvent::Written(tuple.clone())])
     }
 
     fn read(&self, query_tuple: &QueryTuple) -> Result<Option<Tuple>> {
@@ -35,7 +203,53 @@ impl<S: Store> Store for MutexStore<S> {
     }
 
     fn get(&mut self, query_tuple: &QueryTuple) -> Result<Option<Tuple>> {
-        self.store.lock()?.get(query_tuple)
+        let taken = self.store.lock()?.get(query_tuple)?;
+        if let Some(tuple) = &taken {
+            self.notify(&[StoreEvent::Taken(tuple.clone())])?;
+        }
+        Ok(taken)
+    }
+
+    fn read_all(&self, query_tuple: &QueryTuple, limit: Option<usize>) -> Result<Vec<Tuple>> {
+        self.store.lock()?.read_all(query_tuple, limit)
+    }
+
+    fn get_all(&mut self, query_tuple: &QueryTuple, limit: Option<usize>) -> Result<Vec<Tuple>> {
+        let taken = self.store.lock()?.get_all(query_tuple, limit)?;
+        let events: Vec<StoreEvent> = taken.iter().cloned().map(StoreEvent::Taken).collect();
+        self.notify(&events)?;
+        Ok(taken)
+    }
+
+    fn count(&self, query_tuple: &QueryTuple) -> Result<usize> {
+        self.store.lock()?.count(query_tuple)
+    }
+
+    fn write_with_expiry(&mut self, tuple: &Tuple, expires_at: u64) -> Result<()> {
+        self.store.lock()?.write_with_expiry(tuple, expires_at)?;
+        self.written.notify_all();
+        self.notify(&[StoreEvent::Written(tuple.clone())])
+    }
+
+    fn expire(&mut self, now: u64) -> Result<usize> {
+        self.store.lock()?.expire(now)
+    }
+
+    fn next_expiry(&self) -> Result<Option<u64>> {
+        self.store.lock()?.next_expiry()
+    }
+
+    /// Takes and writes under a single lock, so no other handle sees the store in between.
+    fn replace(&mut self, query_tuple: &QueryTuple, tuple: &Tuple) -> Result<Option<Tuple>> {
+        let replaced = self.store.lock()?.replace(query_tuple, tuple)?;
+        if let Some(taken) = &replaced {
+            self.written.notify_all();
+            self.notify(&[
+                StoreEvent::Taken(taken.clone()),
+                StoreEvent::Written(tuple.clone()),
+            ])?;
+        }
+        Ok(replaced)
     }
 }
 
@@ -90,3 +304,215 @@ fn test_mutex_store() -> Result<()> {
 
     Ok(())
 }
+
+#[test]
+fn test_mutex_store_blocking() -> Result<()> {
+    use crate::vec_store::VecStore;
+    use std::thread;
+
+    let mut tuple_space = MutexStore::<VecStore>::default();
+    let query_tuple = QueryTuple::builder().string("job").any_integer().build();
+
+    if let Some(_tuple) = tuple_space.get_blocking(&query_tuple, Some(Duration::from_millis(50)))? {
+        panic!("Tuple found")
+    }
+
+    let reader_tuple_space = tuple_space.clone();
+    let reader_query_tuple = query_tuple.clone();
+    let reader_thread =
+        thread::spawn(move || reader_tuple_space.read_blocking(&reader_query_tuple, None));
+
+    let mut writer_tuple_space = tuple_space.clone();
+    let writer_thread = thread::spawn(move || {
+        thread::sleep(Duration::from_millis(100));
+        writer_tuple_space.write(&Tuple::builder().string("job").integer(1).build())
+    });
+
+    match reader_thread.join() {
+        Ok(Ok(Some(tuple))) => assert_eq!(query_tuple, tuple),
+        _ => panic!("Reader did not see the tuple"),
+    }
+    if writer_thread.join().is_err() {
+        panic!("Writer panic")
+    }
+
+    match tuple_space.get_blocking(&query_tuple, Some(Duration::from_millis(50)))? {
+        Some(tuple) => assert_eq!(query_tuple, tuple),
+        None => panic!("No tuple found"),
+    }
+
+    assert_eq!(0, tuple_space.size()?);
+
+    Ok(())
+}
+
+#[test]
+fn test_mutex_store_replace() -> Result<()> {
+    use crate::vec_store::VecStore;
+
+    let mut tuple_space = MutexStore::<VecStore>::default();
+    let counter_query_tuple = QueryTuple::builder()
+        .string("counter")
+        .any_integer()
+        .build();
+
+    let missing = tuple_space.replace(
+        &counter_query_tuple,
+        &Tuple::builder().string("counter").integer(1).build(),
+    )?;
+    assert!(missing.is_none());
+    assert_eq!(0, tuple_space.size()?);
+
+    tuple_space.write(&Tuple::builder().string("counter").integer(0).build())?;
+    let threads: Vec<_> = (0..4)
+        .map(|_| {
+            let mut thread_tuple_space = tuple_space.clone();
+            let counter_query_tuple = counter_query_tuple.clone();
+            thread::spawn(move || {
+                let mut increments = 0;
+                while increments < 25 {
+                    // Compare-and-swap: only replaces the counter if nobody bumped it since.
+                    let counter = thread_tuple_space.read(&counter_query_tuple).unwrap();
+                    let value = match counter.as_ref().map(|counter| &counter[1]) {
+                        Some(crate::types::Types::Integer(value)) => *value,
+                        _ => continue,
+                    };
+                    let replaced = thread_tuple_space
+                        .replace(
+                            &QueryTuple::builder()
+                                .string("counter")
+                                .integer(value)
+                                .build(),
+                            &Tuple::builder()
+                                .string("counter")
+                                .integer(value + 1)
+                                .build(),
+                        )
+                        .unwrap();
+                    if replaced.is_some() {
+                        increments += 1;
+                    }
+                }
+            })
+        })
+        .collect();
+    for test_thread in threads {
+        if let Err(err) = test_thread.join() {
+            panic!("{:?}", err);
+        }
+    }
+
+    let replaced = tuple_space.replace(
+        &counter_query_tuple,
+        &Tuple::builder().string("counter").integer(0).build(),
+    )?;
+    assert_eq!(
+        Some(Tuple::builder().string("counter").integer(100).build()),
+        replaced
+    );
+    assert_eq!(1, tuple_space.size()?);
+
+    Ok(())
+}
+
+#[test]
+fn test_mutex_store_sweeper() -> Result<()> {
+    use crate::indexed_store::IndexedStore;
+
+    let mut tuple_space = MutexStore::<IndexedStore>::default();
+    let sweeper = tuple_space.spawn_sweeper(Duration::from_millis(10));
+
+    tuple_space.write_with_ttl(
+        &Tuple::builder().string("heartbeat").build(),
+        Duration::from_millis(20),
+    )?;
+    tuple_space.write(&Tuple::builder().string("job").build())?;
+    assert_eq!(2, tuple_space.size()?);
+
+    thread::sleep(Duration::from_millis(200));
+    assert_eq!(1, tuple_space.size()?);
+
+    drop(tuple_space);
+    if let Err(err) = sweeper.join() {
+        panic!("{:?}", err);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_mutex_store_observers() -> Result<()> {
+    use crate::vec_store::VecStore;
+
+    let mut tuple_space = MutexStore::<VecStore>::default();
+    let job_query_tuple = QueryTuple::builder().string("job").any_integer().build();
+    let (job_observer, jobs) = tuple_space.subscribe(job_query_tuple.clone())?;
+
+    let seen = Arc::new(Mutex::new(Vec::new()));
+    let callback_seen = seen.clone();
+    let any_observer = tuple_space
+        .observe(QueryTuple::builder().any().any().build(), move |event| {
+            callback_seen.lock().unwrap().push(event.clone())
+        })?;
+
+    let job = Tuple::builder().string("job").integer(1).build();
+    let other = Tuple::builder().string("other").integer(1).build();
+    tuple_space.write(&job)?;
+    tuple_space.write(&other)?;
+    tuple_space.read(&job_query_tuple)?;
+    tuple_space.get(&job_query_tuple)?;
+    tuple_space.replace(
+        &QueryTuple::builder().string("other").any_integer().build(),
+        &Tuple::builder().string("job").integer(2).build(),
+    )?;
+
+    assert_eq!(StoreEvent::Written(job.clone()), jobs.try_recv().unwrap());
+    assert_eq!(StoreEvent::Taken(job.clone()), jobs.try_recv().unwrap());
+    assert_eq!(
+        StoreEvent::Written(Tuple::builder().string("job").integer(2).build()),
+        jobs.try_recv().unwrap()
+    );
+    assert!(jobs.try_recv().is_err());
+    assert_eq!(5, seen.lock().unwrap().len());
+    assert_eq!(StoreEvent::Taken(other), seen.lock().unwrap()[3]);
+
+    assert!(tuple_space.unobserve(any_observer)?);
+    assert!(!tuple_space.unobserve(any_observer)?);
+    tuple_space.get_all(&job_query_tuple, None)?;
+    assert_eq!(5, seen.lock().unwrap().len());
+    assert!(jobs.try_recv().is_ok());
+
+    // A dropped receiver unregisters its observer on the next event.
+    drop(jobs);
+    tuple_space.write(&job)?;
+    assert!(!tuple_space.unobserve(job_observer)?);
+
+    Ok(())
+}
+
+#[test]
+fn test_mutex_store_atomically() -> Result<()> {
+    use crate::indexed_store::{IndexedStore, StoreImage};
+    use crate::policy::MatchPolicy;
+
+    let tuple_space = MutexStore::new(IndexedStore::with_policy(MatchPolicy::Random { seed: 7 }));
+    let query_tuple = QueryTuple::builder().string("job").any_integer().build();
+    let mut setup = tuple_space.clone();
+    setup.write(&Tuple::builder().string("job").integer(1).build())?;
+    setup.write_with_expiry(&Tuple::builder().string("job").integer(2).build(), 1_000)?;
+    let before = StoreImage::from(tuple_space.to_inner()?);
+
+    let aborted = tuple_space.atomically(|store| -> Result<()> {
+        store.get(&query_tuple)?;
+        store.write(&Tuple::builder().string("job").integer(3).build())?;
+        Err(Error::NotFound)
+    })?;
+    assert!(aborted.is_err());
+    assert_eq!(before, StoreImage::from(tuple_space.to_inner()?));
+
+    let taken = tuple_space.atomically(|store| store.get(&query_tuple))??;
+    assert!(taken.is_some());
+    assert_eq!(1, tuple_space.size()?);
+
+    Ok(())
+}
@@ -0,0 +1,114 @@
+use std::fmt;
+use std::sync::{mpsc, Arc, Mutex};
+
+use crate::error::Error;
+use crate::query_tuple::QueryTuple;
+use crate::result::Result;
+use crate::tuple::Tuple;
+
+/// A change to a [`crate::mutex_store::MutexStore`], as seen by its observers.
+#[derive(Clone, Debug, PartialEq)]
+pub enum StoreEvent {
+    /// The tuple was written, by `write`, `write_with_expiry` or `replace`.
+    Written(Tuple),
+    /// The tuple was taken, by `get`, `get_all` or `replace`.
+    Taken(Tuple),
+}
+
+impl StoreEvent {
+    pub fn tuple(&self) -> &Tuple {
+        match self {
+            StoreEvent::Written(tuple) | StoreEvent::Taken(tuple) => tuple,
+        }
+    }
+}
+
+/// Names an observer to [`crate::mutex_store::MutexStore::unobserve`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct ObserverId(u64);
+
+/// Called with every matching event. Returning `false` unregisters the observer.
+type Callback = Arc<dyn Fn(&StoreEvent) -> bool + Send + Sync>;
+
+/// The observers registered on a store, shared by all its handles.
+#[derive(Default)]
+pub(crate) struct Observers {
+    next_id: u64,
+    observers: Vec<(ObserverId, QueryTuple, Callback)>,
+}
+
+impl Observers {
+    pub(crate) fn add(&mut self, query_tuple: QueryTuple, callback: Callback) -> ObserverId {
+        let id = ObserverId(self.next_id);
+        self.next_id += 1;
+        self.observers.push((id, query_tuple, callback));
+        id
+    }
+
+    pub(crate) fn add_channel(
+        &mut self,
+        query_tuple: QueryTuple,
+    ) -> (ObserverId, mpsc::Receiver<StoreEvent>) {
+        let (sender, receiver) = mpsc::channel();
+        // A dropped receiver unregisters the observer on the next matching event.
+        let callback: Callback =
+            Arc::new(move |event: &StoreEvent| sender.send(event.clone()).is_ok());
+        (self.add(query_tuple, callback), receiver)
+    }
+
+    pub(crate) fn remove(&mut self, id: ObserverId) -> bool {
+        let len = self.observers.len();
+        self.observers
+            .retain(|(observer_id, _, _)| *observer_id != id);
+        self.observers.len() != len
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.observers.is_empty()
+    }
+}
+
+impl fmt::Debug for Observers {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list()
+            .entries(
+                self.observers
+                    .iter()
+                    .map(|(id, query_tuple, _)| (id, query_tuple)),
+            )
+            .finish()
+    }
+}
+
+/// Hands `events` to every observer in `observers` whose query matches, in order.
+///
+/// The callbacks run without the lock held, so they may use the store and register or remove
+/// observers themselves.
+pub(crate) fn notify(observers: &Mutex<Observers>, events: &[StoreEvent]) -> Result<()> {
+    if events.is_empty() {
+        return Ok(());
+    }
+    let matching: Vec<(ObserverId, QueryTuple, Callback)> = {
+        let observers = observers.lock().map_err(|_| Error::MutexPoisonError)?;
+        if observers.is_empty() {
+            return Ok(());
+        }
+        observers.observers.clone()
+    };
+    let mut finished = Vec::new();
+    for (id, query_tuple, callback) in &matching {
+        for event in events {
+            if query_tuple == event.tuple() && !callback(event) {
+                finished.push(*id);
+                break;
+            }
+        }
+    }
+    if !finished.is_empty() {
+        let mut observers = observers.lock().map_err(|_| Error::MutexPoisonError)?;
+        for id in finished {
+            observers.remove(id);
+        }
+    }
+    Ok(())
+}
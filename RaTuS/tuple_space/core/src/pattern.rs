@@ -0,0 +1,130 @@
+use regex::Regex;
+
+use crate::error::Error;
+use crate::result::Result;
+
+/// A string pattern compiled once, when the query is built or deserialized, and then reused for
+/// every tuple it is matched against.
+#[derive(Debug, Clone)]
+pub struct Pattern {
+    source: String,
+    regex: Regex,
+}
+
+impl Pattern {
+    /// Compiles a regular expression. As usual for regexes, it matches anywhere in the string
+    /// unless anchored with `^`/`$`.
+    pub fn regex(source: &str) -> Result<Pattern> {
+        Self::compile(source, source)
+    }
+
+    /// Compiles a shell-style glob matching the whole string: `*` matches any sequence, `?` any
+    /// single character and `[abc]`/`[!abc]` a character class.
+    pub fn glob(source: &str) -> Result<Pattern> {
+        Self::compile(source, &glob_to_regex(source))
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.source
+    }
+
+    pub fn is_match(&self, value: &str) -> bool {
+        self.regex.is_match(value)
+    }
+
+    fn compile(source: &str, regex: &str) -> Result<Pattern> {
+        match Regex::new(regex) {
+            Ok(regex) => Ok(Pattern {
+                source: String::from(source),
+                regex,
+            }),
+            Err(error) => Err(Error::InvalidPattern(format!("{:?}: {}", source, error))),
+        }
+    }
+}
+
+fn glob_to_regex(glob: &str) -> String {
+    let mut regex = String::from("^");
+    let mut chars = glob.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '*' => regex.push_str(".*"),
+            '?' => regex.push('.'),
+            '[' => {
+                regex.push('[');
+                let mut class = chars.clone();
+                if class.next() == Some('!') {
+                    regex.push('^');
+                    chars = class;
+                }
+                for c in chars.by_ref() {
+                    if c == ']' {
+                        break;
+                    }
+                    if c == '\\' || c == '[' {
+                        regex.push('\\');
+                    }
+                    regex.push(c);
+                }
+                regex.push(']');
+            }
+            c => regex.push_str(&regex::escape(&c.to_string())),
+        }
+    }
+    regex.push('$');
+    regex
+}
+
+/// Serializes a regex [`Pattern`] as its source string, recompiling it on deserialization.
+pub(crate) mod serde_regex {
+    use super::Pattern;
+    use serde::{de::Error, Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(pattern: &Pattern, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(pattern.as_str())
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Pattern, D::Error> {
+        let source = String::deserialize(deserializer)?;
+        Pattern::regex(&source).map_err(|error| D::Error::custom(format!("{:?}", error)))
+    }
+}
+
+/// Serializes a glob [`Pattern`] as its source string, recompiling it on deserialization.
+pub(crate) mod serde_glob {
+    use super::Pattern;
+    use serde::{de::Error, Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(pattern: &Pattern, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(pattern.as_str())
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Pattern, D::Error> {
+        let source = String::deserialize(deserializer)?;
+        Pattern::glob(&source).map_err(|error| D::Error::custom(format!("{:?}", error)))
+    }
+}
+
+#[test]
+fn test_pattern() -> Result<()> {
+    let glob = Pattern::glob("job-*")?;
+    assert!(glob.is_match("job-1"));
+    assert!(glob.is_match("job-"));
+    assert!(!glob.is_match("my-job-1"));
+
+    let glob = Pattern::glob("job-?.[!a-c]")?;
+    assert!(glob.is_match("job-1.d"));
+    assert!(!glob.is_match("job-1.a"));
+    assert!(!glob.is_match("job-12.d"));
+
+    let regex = Pattern::regex("^job-[0-9]+$")?;
+    assert!(regex.is_match("job-42"));
+    assert!(!regex.is_match("job-x"));
+
+    match Pattern::regex("job-(") {
+        Err(Error::InvalidPattern(_)) => (),
+        _ => panic!("Invalid pattern accepted"),
+    }
+
+    Ok(())
+}
@@ -0,0 +1,98 @@
+use serde::{Deserialize, Serialize};
+
+/// Which of several matching tuples a store hands out first.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Default)]
+pub enum MatchPolicy {
+    /// The match that was written first.
+    #[default]
+    OldestFirst,
+    /// The match that was written last.
+    NewestFirst,
+    /// A pseudo-random match from a generator seeded with `seed`. The generator state is part of
+    /// the store, so two stores built with the same seed and fed the same operations pick the
+    /// same tuples.
+    Random { seed: u64 },
+}
+
+/// Applies a [`MatchPolicy`] to the matches of a query, carrying the generator state for
+/// [`MatchPolicy::Random`].
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+pub(crate) struct Selector {
+    policy: MatchPolicy,
+    state: u64,
+}
+
+impl Selector {
+    pub(crate) fn new(policy: MatchPolicy) -> Self {
+        let state = match policy {
+            MatchPolicy::Random { seed } => seed,
+            _ => 0,
+        };
+        Self { policy, state }
+    }
+
+    /// Picks up a generator at `state`, as saved from [`Selector::state`].
+    pub(crate) fn resume(policy: MatchPolicy, state: u64) -> Self {
+        Self { policy, state }
+    }
+
+    pub(crate) fn policy(&self) -> MatchPolicy {
+        self.policy
+    }
+
+    pub(crate) fn state(&self) -> u64 {
+        self.state
+    }
+
+    /// Orders `candidates`, given oldest first, by the policy and keeps at most `limit` of them.
+    ///
+    /// Only a random pick advances the generator; callers that must not change the store, like
+    /// reads, select on a copy.
+    pub(crate) fn select<T>(&mut self, mut candidates: Vec<T>, limit: Option<usize>) -> Vec<T> {
+        let limit = limit.unwrap_or(usize::MAX).min(candidates.len());
+        match self.policy {
+            MatchPolicy::OldestFirst => (),
+            MatchPolicy::NewestFirst => candidates.reverse(),
+            MatchPolicy::Random { .. } => {
+                // Partial Fisher-Yates: only the first `limit` positions are drawn.
+                for index in 0..limit {
+                    let remaining = (candidates.len() - index) as u64;
+                    let pick = index + (self.next() % remaining) as usize;
+                    candidates.swap(index, pick);
+                }
+            }
+        }
+        candidates.truncate(limit);
+        candidates
+    }
+
+    /// SplitMix64, small and good enough to spread picks across matches.
+    fn next(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+}
+
+#[test]
+fn test_selector() {
+    let candidates = vec![1, 2, 3, 4, 5];
+
+    let mut oldest = Selector::new(MatchPolicy::OldestFirst);
+    assert_eq!(vec![1, 2], oldest.select(candidates.clone(), Some(2)));
+
+    let mut newest = Selector::new(MatchPolicy::NewestFirst);
+    assert_eq!(vec![5, 4, 3, 2, 1], newest.select(candidates.clone(), None));
+
+    let mut random = Selector::new(MatchPolicy::Random { seed: 7 });
+    let mut replica = random.clone();
+    let picks = random.select(candidates.clone(), Some(3));
+    assert_eq!(3, picks.len());
+    assert_eq!(picks, replica.select(candidates.clone(), Some(3)));
+
+    let mut all = random.select(candidates.clone(), None);
+    all.sort();
+    assert_eq!(candidates, all);
+}
@@ -1,5 +1,7 @@
+use crate::pattern::Pattern;
+use crate::result::Result;
 use crate::tuple::Tuple;
-use crate::types::QueryTypes;
+use crate::types::{QueryTypes, Types};
 use serde::{Deserialize, Serialize};
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -19,19 +21,92 @@ impl QueryTuple {
     pub fn builder() -> QueryTupleBuilder {
         QueryTupleBuilder::default()
     }
+
+    /// Lengths of the tuples this query can match: the minimum, and the maximum unless the query
+    /// has a `Rest` field, optional or not.
+    pub fn arity(&self) -> (usize, Option<usize>) {
+        let mut min = 0;
+        let mut max = Some(0);
+        for field in self.query_tuple.iter() {
+            let (field_min, field_max) = width(field);
+            min += field_min;
+            max = max.zip(field_max).map(|(max, field_max)| max + field_max);
+        }
+        (min, max)
+    }
+
+    /// Whether the query has `Optional` or `Rest` fields, which match a varying number of fields.
+    fn is_variable(&self) -> bool {
+        self.query_tuple
+            .iter()
+            .any(|field| matches!(field, QueryTypes::Rest | QueryTypes::Optional(_)))
+    }
+}
+
+/// How many tuple fields `field` matches: at least, and at most unless it is a `Rest`.
+fn width(field: &QueryTypes) -> (usize, Option<usize>) {
+    match field {
+        QueryTypes::Rest => (0, None),
+        QueryTypes::Optional(field) => (0, width(field).1),
+        _ => (1, Some(1)),
+    }
+}
+
+/// Where matching `field` can leave off in `rhs`, given where it can start: `reachable[index]`
+/// tells whether the fields before it can match `rhs[..index]`.
+fn step(field: &QueryTypes, rhs: &Tuple, reachable: &[bool]) -> Vec<bool> {
+    let mut next = vec![false; reachable.len()];
+    match field {
+        QueryTypes::Rest => {
+            let mut started = false;
+            for (next, reachable) in next.iter_mut().zip(reachable) {
+                started |= *reachable;
+                *next = started;
+            }
+        }
+        QueryTypes::Optional(field) => {
+            next = step(field, rhs, reachable);
+            for (next, reachable) in next.iter_mut().zip(reachable) {
+                *next |= *reachable;
+            }
+        }
+        field => {
+            for index in 0..rhs.len() {
+                next[index + 1] = reachable[index] && *field == rhs[index];
+            }
+        }
+    }
+    next
 }
 
 impl PartialEq<Tuple> for QueryTuple {
+    /// With `Optional` or `Rest` fields, tracks every tuple position the fields so far can reach,
+    /// so a query costs at most its length times the tuple's, however the fields are placed.
     fn eq(&self, rhs: &Tuple) -> bool {
-        if self.len() != rhs.len() {
-            return false;
+        if !self.is_variable() {
+            return self.len() == rhs.len()
+                && (0..self.len()).all(|index| self[index] == rhs[index]);
         }
-        for i in 0..self.len() {
-            if self[i] != rhs[i] {
+        let mut reachable = vec![false; rhs.len() + 1];
+        reachable[0] = true;
+        for field in self.query_tuple.iter() {
+            reachable = step(field, rhs, &reachable);
+            if !reachable.contains(&true) {
                 return false;
             }
         }
-        true
+        reachable[rhs.len()]
+    }
+}
+
+/// The query matching exactly `tuple`, field by field.
+impl From<&Tuple> for QueryTuple {
+    fn from(tuple: &Tuple) -> Self {
+        QueryTuple {
+            query_tuple: (0..tuple.len())
+                .map(|index| QueryTypes::from(&tuple[index]))
+                .collect(),
+        }
     }
 }
 
@@ -54,6 +129,28 @@ impl QueryTupleBuilder {
         QueryTuple { query_tuple }
     }
 
+    /// Matches any number of further fields, including none: `("event", rest)` matches every
+    /// tuple whose first field is `"event"`.
+    pub fn rest(mut self) -> Self {
+        self.query_tuple.push(QueryTypes::Rest);
+        self
+    }
+
+    /// Makes the previously added field optional, so the query also matches tuples where that
+    /// field is missing, e.g. `Tuple::query().string("event").integer(1).optional()`.
+    ///
+    /// # Panics
+    ///
+    /// If no field was added before.
+    pub fn optional(mut self) -> Self {
+        let field = self
+            .query_tuple
+            .pop()
+            .expect("optional() must follow the field it makes optional");
+        self.query_tuple.push(QueryTypes::Optional(Box::new(field)));
+        self
+    }
+
     pub fn any(mut self) -> Self {
         self.query_tuple.push(QueryTypes::Any);
         self
@@ -99,4 +196,147 @@ impl QueryTupleBuilder {
             .push(QueryTypes::ExactString(String::from(string)));
         self
     }
+
+    /// Matches an integer within `min..=max`.
+    pub fn integer_range(mut self, min: i64, max: i64) -> Self {
+        self.query_tuple.push(QueryTypes::IntegerRange(min, max));
+        self
+    }
+
+    /// Matches a float within `min..=max`.
+    pub fn float_range(mut self, min: f64, max: f64) -> Self {
+        self.query_tuple.push(QueryTypes::FloatRange(min, max));
+        self
+    }
+
+    /// Matches a value of the same type as `value` that is strictly greater than it.
+    pub fn greater_than<T: Into<Types>>(mut self, value: T) -> Self {
+        self.query_tuple.push(QueryTypes::GreaterThan(value.into()));
+        self
+    }
+
+    /// Matches a value of the same type as `value` that is strictly less than it.
+    pub fn less_than<T: Into<Types>>(mut self, value: T) -> Self {
+        self.query_tuple.push(QueryTypes::LessThan(value.into()));
+        self
+    }
+
+    pub fn string_prefix(mut self, prefix: &str) -> Self {
+        self.query_tuple
+            .push(QueryTypes::StringPrefix(String::from(prefix)));
+        self
+    }
+
+    pub fn string_suffix(mut self, suffix: &str) -> Self {
+        self.query_tuple
+            .push(QueryTypes::StringSuffix(String::from(suffix)));
+        self
+    }
+
+    /// Matches a string against a glob such as `"job-*"`, see [`Pattern::glob`].
+    pub fn string_matching(mut self, glob: &str) -> Result<Self> {
+        self.query_tuple
+            .push(QueryTypes::StringGlob(Pattern::glob(glob)?));
+        Ok(self)
+    }
+
+    /// Matches a string against a regular expression, see [`Pattern::regex`].
+    pub fn string_regex(mut self, regex: &str) -> Result<Self> {
+        self.query_tuple
+            .push(QueryTypes::StringRegex(Pattern::regex(regex)?));
+        Ok(self)
+    }
+}
+
+#[test]
+fn test_exact_query_from_tuple() {
+    let tuple = Tuple::builder()
+        .string("job")
+        .integer(1)
+        .float(0.5)
+        .boolean(true)
+        .build();
+    let query_tuple = QueryTuple::from(&tuple);
+
+    assert_eq!(query_tuple, tuple);
+    assert!(
+        query_tuple
+            != Tuple::builder()
+                .string("job")
+                .integer(2)
+                .float(0.5)
+                .boolean(true)
+                .build()
+    );
+    assert!(query_tuple != Tuple::builder().string("job").integer(1).float(0.5).build());
+}
+
+#[test]
+fn test_variable_arity() {
+    let event = Tuple::builder().string("event").build();
+    let event_with_id = Tuple::builder().string("event").integer(1).build();
+    let event_with_payload = Tuple::builder()
+        .string("event")
+        .integer(1)
+        .string("payload")
+        .build();
+    let other = Tuple::builder().string("other").integer(1).build();
+
+    let prefix_query = QueryTuple::builder().string("event").rest().build();
+    assert_eq!(prefix_query, event);
+    assert_eq!(prefix_query, event_with_id);
+    assert_eq!(prefix_query, event_with_payload);
+    assert_ne!(prefix_query, other);
+
+    let optional_query = QueryTuple::builder()
+        .string("event")
+        .any_integer()
+        .optional()
+        .build();
+    assert_eq!(optional_query, event);
+    assert_eq!(optional_query, event_with_id);
+    assert_ne!(optional_query, event_with_payload);
+
+    let suffix_query = QueryTuple::builder().rest().string("payload").build();
+    assert_eq!(suffix_query, event_with_payload);
+    assert_ne!(suffix_query, event_with_id);
+}
+
+#[test]
+fn test_variable_arity_cost() {
+    // Every placement of the `rest`s is tried at most once per tuple position.
+    let mut builder = QueryTuple::builder();
+    for _ in 0..64 {
+        builder = builder.rest().any_integer().optional();
+    }
+    let query_tuple = builder.string("end").build();
+    let integers = |builder: crate::tuple::TupleBuilder| {
+        (0..64).fold(builder, |builder, index| builder.integer(index))
+    };
+    assert_ne!(query_tuple, integers(Tuple::builder()).build());
+    assert_eq!(
+        query_tuple,
+        integers(Tuple::builder()).string("end").build()
+    );
+
+    let optional_rest = QueryTuple::builder()
+        .string("event")
+        .rest()
+        .optional()
+        .build();
+    assert_eq!((1, None), optional_rest.arity());
+    assert_eq!(
+        optional_rest,
+        Tuple::builder()
+            .string("event")
+            .integer(1)
+            .integer(2)
+            .build()
+    );
+}
+
+#[test]
+#[should_panic]
+fn test_optional_without_field() {
+    QueryTuple::builder().optional();
 }
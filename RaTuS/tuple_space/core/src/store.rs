@@ -1,10 +1,58 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
 use crate::query_tuple::QueryTuple;
 use crate::result::Result;
 use crate::tuple::Tuple;
 
+/// Milliseconds since the Unix epoch on the local clock, the unit tuple expiry times are given in.
+pub fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| u64::try_from(elapsed.as_millis()).unwrap_or(u64::MAX))
+        .unwrap_or(0)
+}
+
 pub trait Store: Default {
     fn size(&self) -> Result<usize>;
     fn write(&mut self, tuple: &Tuple) -> Result<()>;
     fn read(&self, query_tuple: &QueryTuple) -> Result<Option<Tuple>>;
     fn get(&mut self, query_tuple: &QueryTuple) -> Result<Option<Tuple>>;
+    /// Copies of every tuple matching `query_tuple`, at most `limit` of them if given.
+    fn read_all(&self, query_tuple: &QueryTuple, limit: Option<usize>) -> Result<Vec<Tuple>>;
+    /// Removes every tuple matching `query_tuple`, at most `limit` of them if given.
+    fn get_all(&mut self, query_tuple: &QueryTuple, limit: Option<usize>) -> Result<Vec<Tuple>>;
+    fn count(&self, query_tuple: &QueryTuple) -> Result<usize>;
+    /// Writes `tuple` to be dropped by the first [`Store::expire`] at or after `expires_at`,
+    /// in milliseconds since the Unix epoch. Until then it matches like any other tuple.
+    fn write_with_expiry(&mut self, tuple: &Tuple, expires_at: u64) -> Result<()>;
+    /// Writes `tuple` to expire `ttl` from now on the local clock.
+    fn write_with_ttl(&mut self, tuple: &Tuple, ttl: Duration) -> Result<()> {
+        let ttl = u64::try_from(ttl.as_millis()).unwrap_or(u64::MAX);
+        self.write_with_expiry(tuple, now_millis().saturating_add(ttl))
+    }
+    /// Drops every tuple expiring at or before `now`, returning how many were dropped.
+    fn expire(&mut self, now: u64) -> Result<usize>;
+    /// The earliest expiry time of the stored tuples, `None` if none was written with one.
+    fn next_expiry(&self) -> Result<Option<u64>>;
+    /// Takes one tuple matching `query_tuple` and writes `tuple` in its place, returning the
+    /// taken tuple. Nothing is written if there is no match.
+    fn replace(&mut self, query_tuple: &QueryTuple, tuple: &Tuple) -> Result<Option<Tuple>> {
+        let replaced = self.get(query_tuple)?;
+        if replaced.is_some() {
+            self.write(tuple)?;
+        }
+        Ok(replaced)
+    }
+}
+
+/// A store that can undo the changes made since a checkpoint, so a batch of operations can be
+/// applied all or nothing without copying the store first.
+pub trait Rollback: Store {
+    /// Starts recording changes, replacing any earlier checkpoint.
+    fn begin(&mut self);
+    /// Keeps the changes since [`Rollback::begin`] and stops recording.
+    fn commit(&mut self);
+    /// Puts the store back as it was at [`Rollback::begin`], write order, expiry times and match
+    /// policy state included, and stops recording.
+    fn rollback(&mut self);
 }
@@ -126,5 +126,21 @@ fn test_builder() {
         .any_boolean()
         .any_string()
         .build();
-    assert_eq!(query_tuple, tuple)
+    assert_eq!(query_tuple, tuple);
+
+    let query_tuple = Tuple::query()
+        .integer_range(0, 1)
+        .greater_than(1.5)
+        .any_boolean()
+        .string_prefix("Str")
+        .build();
+    assert_eq!(query_tuple, tuple);
+
+    let query_tuple = Tuple::query()
+        .less_than(1)
+        .float_range(0.0, 3.0)
+        .any_boolean()
+        .string_suffix("ing")
+        .build();
+    assert_ne!(query_tuple, tuple)
 }
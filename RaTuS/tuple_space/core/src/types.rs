@@ -1,5 +1,7 @@
 use serde::{Deserialize, Serialize};
 
+use crate::pattern::Pattern;
+
 macro_rules! tuple_types {
     ($(($type:ty, $name:ident,$exact:ident,$any:ident)),+) => {
         #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -18,6 +20,35 @@ macro_rules! tuple_types {
                         _ => false,
                 }
             }
+
+            /// Orders two values of the same type; values of different types are not comparable.
+            fn compare(&self, other: &Types) -> Option<std::cmp::Ordering> {
+                match (self, other) {
+                    $(
+                        (Self::$name(lhs), Self::$name(rhs)) => lhs.partial_cmp(rhs),
+                    )*
+                        _ => None,
+                }
+            }
+        }
+
+        $(
+            impl From<$type> for Types {
+                fn from(value: $type) -> Self {
+                    Self::$name(value)
+                }
+            }
+        )*
+
+        /// The query field matching exactly `value`.
+        impl From<&Types> for QueryTypes {
+            fn from(value: &Types) -> Self {
+                match value {
+                    $(
+                        Types::$name(inner_value) => Self::$exact(inner_value.clone()),
+                    )*
+                }
+            }
         }
 
         impl std::fmt::Display for Types {
@@ -39,13 +70,55 @@ macro_rules! tuple_types {
                 $exact($type),
                 $any,
             )*
+            /// An integer within `min..=max`.
+            IntegerRange(i64, i64),
+            /// A float within `min..=max`.
+            FloatRange(f64, f64),
+            /// A value of the same type strictly greater than the given one.
+            GreaterThan(Types),
+            /// A value of the same type strictly less than the given one.
+            LessThan(Types),
+            StringPrefix(String),
+            StringSuffix(String),
+            StringRegex(#[serde(with = "crate::pattern::serde_regex")] Pattern),
+            StringGlob(#[serde(with = "crate::pattern::serde_glob")] Pattern),
+            /// Zero or more trailing fields of any type.
+            Rest,
+            /// A field that may be missing; when present it must satisfy the inner query.
+            Optional(Box<QueryTypes>),
         }
 
 
         impl QueryTypes {
+            /// The value this field requires exactly, if it is an exact match.
+            pub fn exact_value(&self) -> Option<Types> {
+                match self {
+                    $(
+                        Self::$exact(value) => Some(Types::$name(value.clone())),
+                    )*
+                    _ => None,
+                }
+            }
+
             fn satisfy(&self, other: &Types) -> bool {
                 match(self, other) {
                     (Self::Any, _) => true,
+                    (Self::Rest, _) => true,
+                    (Self::Optional(field), value) => field.satisfy(value),
+                    (Self::IntegerRange(min, max), Types::Integer(value)) => min <= value && value <= max,
+                    (Self::IntegerRange(..), _) => false,
+                    (Self::FloatRange(min, max), Types::Float(value)) => min <= value && value <= max,
+                    (Self::FloatRange(..), _) => false,
+                    (Self::GreaterThan(bound), value) => value.compare(bound) == Some(std::cmp::Ordering::Greater),
+                    (Self::LessThan(bound), value) => value.compare(bound) == Some(std::cmp::Ordering::Less),
+                    (Self::StringPrefix(prefix), Types::String(value)) => value.starts_with(prefix.as_str()),
+                    (Self::StringPrefix(_), _) => false,
+                    (Self::StringSuffix(suffix), Types::String(value)) => value.ends_with(suffix.as_str()),
+                    (Self::StringSuffix(_), _) => false,
+                    (Self::StringRegex(pattern), Types::String(value)) => pattern.is_match(value),
+                    (Self::StringRegex(_), _) => false,
+                    (Self::StringGlob(pattern), Types::String(value)) => pattern.is_match(value),
+                    (Self::StringGlob(_), _) => false,
                     $(
                         (Self::$any, Types::$name(_)) => true,
                         (Self::$any, _) => false,
@@ -67,6 +140,12 @@ tuple_types![
     (String, String, ExactString, AnyString)
 ];
 
+impl From<&str> for Types {
+    fn from(value: &str) -> Self {
+        Types::String(String::from(value))
+    }
+}
+
 impl PartialEq<Types> for QueryTypes {
     fn eq(&self, other: &Types) -> bool {
         self.satisfy(other)
@@ -133,3 +212,27 @@ fn test_query_compare() {
     assert_eq!(QueryTypes::ExactFloat(1.0), float);
     assert_eq!(QueryTypes::ExactBoolean(true), boolean);
 }
+
+#[test]
+fn test_query_predicates() {
+    let integer = Types::Integer(5);
+    let float = Types::Float(1.5);
+    let string = Types::String(String::from("job-42"));
+
+    assert_eq!(QueryTypes::IntegerRange(1, 5), integer);
+    assert_ne!(QueryTypes::IntegerRange(6, 10), integer);
+    assert_ne!(QueryTypes::IntegerRange(1, 5), float);
+    assert_eq!(QueryTypes::FloatRange(1.0, 2.0), float);
+    assert_ne!(QueryTypes::FloatRange(2.0, 3.0), float);
+
+    assert_eq!(QueryTypes::GreaterThan(Types::from(4)), integer);
+    assert_ne!(QueryTypes::GreaterThan(Types::from(5)), integer);
+    assert_ne!(QueryTypes::GreaterThan(Types::from(4.0)), integer);
+    assert_eq!(QueryTypes::LessThan(Types::from(2.0)), float);
+    assert_eq!(QueryTypes::LessThan(Types::from("job-5")), string);
+
+    assert_eq!(QueryTypes::StringPrefix(String::from("job-")), string);
+    assert_ne!(QueryTypes::StringPrefix(String::from("task-")), string);
+    assert_eq!(QueryTypes::StringSuffix(String::from("42")), string);
+    assert_ne!(QueryTypes::StringSuffix(String::from("42")), integer);
+}
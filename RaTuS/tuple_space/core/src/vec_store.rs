@@ -1,15 +1,25 @@
 use serde::{Deserialize, Serialize};
 
+use crate::policy::{MatchPolicy, Selector};
 use crate::query_tuple::QueryTuple;
 use crate::result::Result;
 use crate::store::Store;
 use crate::tuple::Tuple;
 
+#[derive(Clone, Serialize, Deserialize, Debug)]
+struct Entry {
+    tuple: Tuple,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    expires_at: Option<u64>,
+}
+
 #[derive(Clone, Serialize, Deserialize, Debug)]
 pub struct VecStore {
-    inner: Vec<Option<Tuple>>,
+    inner: Vec<Option<Entry>>,
     tuple_count: usize,
     compact_margin: f64,
+    #[serde(default)]
+    selector: Selector,
 }
 
 pub const DEFAULT_COMPACT_MARGIN: f64 = 0.9;
@@ -23,6 +33,10 @@ impl VecStore {
         self.compact_margin
     }
 
+    pub fn policy(&self) -> MatchPolicy {
+        self.selector.policy()
+    }
+
     fn compact(&mut self) {
         let current_compact_margin = self.tuple_count as f64 / self.inner.len() as f64;
         if current_compact_margin < self.compact_margin {
@@ -30,18 +44,52 @@ impl VecStore {
         }
     }
 
+    fn push(&mut self, tuple: &Tuple, expires_at: Option<u64>) {
+        self.compact();
+        self.inner.push(Some(Entry {
+            tuple: tuple.clone(),
+            expires_at,
+        }));
+        self.tuple_count += 1;
+    }
+
+    fn take(&mut self, index: usize) -> Option<Tuple> {
+        self.inner[index].take().map(|entry| entry.tuple)
+    }
+
     pub fn builder() -> VecStoreBuilder {
         VecStoreBuilder::default()
     }
 
-    fn index_of(&self, query_tuple: &QueryTuple) -> Option<usize> {
-        self.inner.iter().position(|vec_element| {
-            if let Some(tuple) = vec_element {
-                query_tuple == tuple
-            } else {
-                false
+    /// Positions of the tuples matching `query_tuple`, at most `limit` of them, in the order
+    /// given by the store's policy. `selector` is the store's own, or a copy of it for reads.
+    fn select(
+        &self,
+        selector: &mut Selector,
+        query_tuple: &QueryTuple,
+        limit: Option<usize>,
+    ) -> Vec<usize> {
+        let limit = limit.unwrap_or(usize::MAX);
+        match selector.policy() {
+            MatchPolicy::OldestFirst => self.indices_of(query_tuple).take(limit).collect(),
+            MatchPolicy::NewestFirst => self.indices_of(query_tuple).rev().take(limit).collect(),
+            MatchPolicy::Random { .. } => {
+                selector.select(self.indices_of(query_tuple).collect(), Some(limit))
             }
-        })
+        }
+    }
+
+    fn indices_of<'a>(
+        &'a self,
+        query_tuple: &'a QueryTuple,
+    ) -> impl DoubleEndedIterator<Item = usize> + 'a {
+        self.inner
+            .iter()
+            .enumerate()
+            .filter_map(move |(index, vec_element)| match vec_element {
+                Some(entry) if query_tuple == &entry.tuple => Some(index),
+                _ => None,
+            })
     }
 }
 
@@ -51,6 +99,7 @@ impl Default for VecStore {
             inner: Vec::new(),
             tuple_count: 0,
             compact_margin: DEFAULT_COMPACT_MARGIN,
+            selector: Selector::default(),
         }
     }
 }
@@ -61,33 +110,88 @@ impl Store for VecStore {
     }
 
     fn read(&self, query_tuple: &QueryTuple) -> Result<Option<Tuple>> {
-        match self.index_of(query_tuple) {
-            Some(index) => Ok(self.inner[index].clone()),
+        match self
+            .select(&mut self.selector.clone(), query_tuple, Some(1))
+            .first()
+        {
+            Some(index) => Ok(self.inner[*index].as_ref().map(|entry| entry.tuple.clone())),
             None => Ok(None),
         }
     }
 
     fn write(&mut self, tuple: &Tuple) -> Result<()> {
-        self.compact();
-        self.inner.push(Some(tuple.clone()));
-        self.tuple_count += 1;
+        self.push(tuple, None);
         Ok(())
     }
 
     fn get(&mut self, query_tuple: &QueryTuple) -> Result<Option<Tuple>> {
-        match self.index_of(query_tuple) {
+        let mut selector = self.selector.clone();
+        let selected = self.select(&mut selector, query_tuple, Some(1));
+        self.selector = selector;
+        match selected.first() {
             Some(index) => {
-                let tuple = self.inner[index].take();
+                let tuple = self.take(*index);
                 self.tuple_count -= 1;
                 Ok(tuple)
             }
             None => Ok(None),
         }
     }
+
+    fn read_all(&self, query_tuple: &QueryTuple, limit: Option<usize>) -> Result<Vec<Tuple>> {
+        Ok(self
+            .select(&mut self.selector.clone(), query_tuple, limit)
+            .into_iter()
+            .filter_map(|index| self.inner[index].as_ref().map(|entry| entry.tuple.clone()))
+            .collect())
+    }
+
+    fn get_all(&mut self, query_tuple: &QueryTuple, limit: Option<usize>) -> Result<Vec<Tuple>> {
+        let mut selector = self.selector.clone();
+        let indices = self.select(&mut selector, query_tuple, limit);
+        self.selector = selector;
+        let tuples: Vec<Tuple> = indices
+            .into_iter()
+            .filter_map(|index| self.take(index))
+            .collect();
+        self.tuple_count -= tuples.len();
+        Ok(tuples)
+    }
+
+    fn count(&self, query_tuple: &QueryTuple) -> Result<usize> {
+        Ok(self.indices_of(query_tuple).count())
+    }
+
+    fn write_with_expiry(&mut self, tuple: &Tuple, expires_at: u64) -> Result<()> {
+        self.push(tuple, Some(expires_at));
+        Ok(())
+    }
+
+    fn expire(&mut self, now: u64) -> Result<usize> {
+        let mut expired = 0;
+        for vec_element in self.inner.iter_mut() {
+            if matches!(vec_element, Some(Entry { expires_at: Some(expires_at), .. }) if *expires_at <= now)
+            {
+                *vec_element = None;
+                expired += 1;
+            }
+        }
+        self.tuple_count -= expired;
+        Ok(expired)
+    }
+
+    fn next_expiry(&self) -> Result<Option<u64>> {
+        Ok(self
+            .inner
+            .iter()
+            .filter_map(|vec_element| vec_element.as_ref().and_then(|entry| entry.expires_at))
+            .min())
+    }
 }
 
 pub struct VecStoreBuilder {
     compact_margin: f64,
+    policy: MatchPolicy,
 }
 
 impl VecStoreBuilder {
@@ -96,10 +200,20 @@ impl VecStoreBuilder {
         self
     }
 
+    /// Which matching tuple `read`/`get` hand out first, oldest first by default.
+    pub fn policy(mut self, policy: MatchPolicy) -> Self {
+        self.policy = policy;
+        self
+    }
+
     pub fn build(self) -> VecStore {
-        let VecStoreBuilder { compact_margin } = self;
+        let VecStoreBuilder {
+            compact_margin,
+            policy,
+        } = self;
         VecStore {
             compact_margin,
+            selector: Selector::new(policy),
             ..Default::default()
         }
     }
@@ -109,6 +223,7 @@ impl Default for VecStoreBuilder {
     fn default() -> Self {
         Self {
             compact_margin: DEFAULT_COMPACT_MARGIN,
+            policy: MatchPolicy::default(),
         }
     }
 }
@@ -156,3 +271,126 @@ fn test_vec_store() -> Result<()> {
 
     Ok(())
 }
+
+#[test]
+fn test_vec_store_string_matching() -> Result<()> {
+    let mut tuple_store = VecStore::default();
+
+    tuple_store.write(&Tuple::builder().string("task-1").integer(1).build())?;
+    tuple_store.write(&Tuple::builder().string("job-2").integer(2).build())?;
+
+    let query_tuple = QueryTuple::builder()
+        .string_matching("job-*")?
+        .any_integer()
+        .build();
+    match tuple_store.get(&query_tuple)? {
+        Some(tuple) => assert_eq!(QueryTuple::builder().any().integer(2).build(), tuple),
+        None => panic!("No tuple found"),
+    }
+    assert!(tuple_store.get(&query_tuple)?.is_none());
+
+    let query_tuple = QueryTuple::builder()
+        .string_regex("^task-[0-9]$")?
+        .any_integer()
+        .build();
+    assert!(tuple_store.read(&query_tuple)?.is_some());
+
+    assert!(QueryTuple::builder().string_regex("task-(").is_err());
+
+    Ok(())
+}
+
+#[test]
+fn test_vec_store_bulk() -> Result<()> {
+    let mut tuple_store = VecStore::default();
+
+    for i in 0..5 {
+        tuple_store.write(&Tuple::builder().string("job").integer(i).build())?;
+    }
+    tuple_store.write(&Tuple::builder().string("other").integer(0).build())?;
+
+    let query_tuple = QueryTuple::builder().string("job").any_integer().build();
+
+    assert_eq!(5, tuple_store.count(&query_tuple)?);
+    assert_eq!(5, tuple_store.read_all(&query_tuple, None)?.len());
+    assert_eq!(2, tuple_store.read_all(&query_tuple, Some(2))?.len());
+    assert_eq!(6, tuple_store.size()?);
+
+    let taken = tuple_store.get_all(&query_tuple, Some(3))?;
+    assert_eq!(3, taken.len());
+    assert_eq!(QueryTuple::builder().any().integer(0).build(), taken[0]);
+    assert_eq!(3, tuple_store.size()?);
+
+    assert_eq!(2, tuple_store.get_all(&query_tuple, None)?.len());
+    assert_eq!(0, tuple_store.count(&query_tuple)?);
+    assert_eq!(1, tuple_store.size()?);
+
+    Ok(())
+}
+
+#[test]
+fn test_vec_store_policy() -> Result<()> {
+    let query_tuple = QueryTuple::builder().any_integer().build();
+    let write_all = |tuple_store: &mut VecStore| -> Result<()> {
+        for i in 0..10 {
+            tuple_store.write(&Tuple::builder().integer(i).build())?;
+        }
+        Ok(())
+    };
+
+    let mut oldest_store = VecStore::builder().build();
+    write_all(&mut oldest_store)?;
+    match oldest_store.get(&query_tuple)? {
+        Some(tuple) => assert_eq!(QueryTuple::builder().integer(0).build(), tuple),
+        None => panic!("No tuple found"),
+    }
+
+    let mut newest_store = VecStore::builder().policy(MatchPolicy::NewestFirst).build();
+    write_all(&mut newest_store)?;
+    match newest_store.get(&query_tuple)? {
+        Some(tuple) => assert_eq!(QueryTuple::builder().integer(9).build(), tuple),
+        None => panic!("No tuple found"),
+    }
+
+    let policy = MatchPolicy::Random { seed: 42 };
+    let mut random_store = VecStore::builder().policy(policy).build();
+    let mut replica_store = VecStore::builder().policy(policy).build();
+    write_all(&mut random_store)?;
+    write_all(&mut replica_store)?;
+    for _ in 0..10 {
+        let tuple = random_store.get(&query_tuple)?;
+        let replica_tuple = replica_store.get(&query_tuple)?;
+        match (tuple, replica_tuple) {
+            (Some(tuple), Some(replica_tuple)) => assert_eq!(tuple, replica_tuple),
+            _ => panic!("No tuple found"),
+        }
+    }
+    assert_eq!(0, random_store.size()?);
+
+    Ok(())
+}
+
+#[test]
+fn test_vec_store_expiry() -> Result<()> {
+    let mut tuple_store = VecStore::default();
+
+    tuple_store.write_with_expiry(&Tuple::builder().string("lock").build(), 100)?;
+    tuple_store.write(&Tuple::builder().string("lock").build())?;
+    tuple_store.write_with_ttl(
+        &Tuple::builder().string("heartbeat").build(),
+        std::time::Duration::from_secs(60),
+    )?;
+
+    assert_eq!(Some(100), tuple_store.next_expiry()?);
+    assert_eq!(1, tuple_store.expire(100)?);
+    assert_eq!(2, tuple_store.size()?);
+    assert_eq!(0, tuple_store.expire(100)?);
+    assert!(tuple_store.next_expiry()?.unwrap() > 100);
+    assert_eq!(1, tuple_store.expire(u64::MAX)?);
+    assert_eq!(None, tuple_store.next_expiry()?);
+    assert!(tuple_store
+        .read(&QueryTuple::builder().string("lock").build())?
+        .is_some());
+
+    Ok(())
+}
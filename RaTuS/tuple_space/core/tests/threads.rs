@@ -4,6 +4,7 @@ mod tests {
     use std::{thread, time};
     
     use ts_core::mutex_store::MutexStore;
+    use ts_core::observer::StoreEvent;
     use ts_core::query_tuple::QueryTuple;
     use ts_core::result::Result;
     use ts_core::store::Store;
@@ -18,6 +19,8 @@ mod tests {
 
         Logger::info("Starting", true);
         let mutex_store = MutexStore::<VecStore>::default();
+        // Subscribed before any write, so the reader sees every tuple written.
+        let (_, events) = mutex_store.subscribe(QueryTuple::builder().any().any().build()).unwrap();
     
         let mut writer_tuple_space = mutex_store.clone();
         let writer_1_thread: JoinHandle<Result<()>> = thread::spawn(move || {
@@ -35,40 +38,39 @@ mod tests {
             Ok(())
         });
     
-        thread::sleep(time::Duration::from_millis(200));
-    
-        let reader_mutex_store = mutex_store.clone();
-        let reader_thread: JoinHandle<Result<()>> = thread::spawn(move || {
+        let reader_thread: JoinHandle<usize> = thread::spawn(move || {
             Logger::info("Spawning Reader", true);
             let mut num_tuples = 0;
-            let query_tuple = QueryTuple::builder().any().any().build();
-            let reader_sleep = time::Duration::from_millis(500);
     
-            while let Some(tuple) = reader_mutex_store.read(&query_tuple)? {
-                Logger::info(&format!("Reader: Read: {:?}", tuple), true);
-                num_tuples += 1;
-                thread::sleep(reader_sleep);
+            while num_tuples < 200 {
+                match events.recv() {
+                    Ok(StoreEvent::Written(tuple)) => {
+                        Logger::info(&format!("Reader: Saw written: {:?}", tuple), true);
+                        num_tuples += 1;
+                    }
+                    Ok(StoreEvent::Taken(_)) => (),
+                    Err(_) => break,
+                }
             }
     
-            Logger::info(&format!("Reader: Tuple space empty! I read {} tuples.", num_tuples), true);
-            Ok(())
+            Logger::info(&format!("Reader: Saw {} tuples written.", num_tuples), true);
+            num_tuples
         });
     
         let mut getter_mutex_store = mutex_store.clone();
-        let getter_thread: JoinHandle<Result<()>> = thread::spawn(move || {
+        let getter_thread: JoinHandle<Result<usize>> = thread::spawn(move || {
             Logger::info("Spawning getter", true);
             let mut num_tuples = 0;
             let query_tuple = QueryTuple::builder().any_integer().any_integer().build();
-            let getter_sleep = time::Duration::from_millis(110);
+            let getter_timeout = time::Duration::from_millis(1000);
     
-            while let Some(tuple) = getter_mutex_store.get(&query_tuple)? {
+            while let Some(tuple) = getter_mutex_store.get_blocking(&query_tuple, Some(getter_timeout))? {
                 Logger::info(&format!("getter: Took: {:?}", tuple), true);
                 num_tuples += 1;
-                thread::sleep(getter_sleep);
             }
     
             Logger::info(&format!("getter: Tuple space empty! I took {} tuples.", num_tuples), true);
-            Ok(())
+            Ok(num_tuples)
         });
     
         let mut writer_mutex_store = mutex_store.clone();
@@ -93,11 +95,13 @@ mod tests {
         if let Err(_) = writer_2_thread.join() {
             panic!("Writer 2 panic")
         };
-        if let Err(_) = getter_thread.join() {
-            panic!("getter panic")
+        match getter_thread.join() {
+            Ok(Ok(num_tuples)) => assert_eq!(200, num_tuples),
+            _ => panic!("getter panic"),
         };
-        if let Err(_) = reader_thread.join() {
-            panic!("Reader panic")
+        match reader_thread.join() {
+            Ok(num_tuples) => assert_eq!(200, num_tuples),
+            Err(_) => panic!("Reader panic"),
         };
     
         Logger::info("Finished", true);
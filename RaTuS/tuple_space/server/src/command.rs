@@ -1,10 +1,21 @@
+use std::time::Duration;
 use ts_core::query_tuple::QueryTuple;
 use ts_core::tuple::Tuple;
 
 #[derive(Debug)]
 pub(crate) enum Command {
     Size,
-    Write(Tuple),
+    /// A tuple to write, expiring after the duration if one is given.
+    Write(Tuple, Option<Duration>),
     Read(QueryTuple),
     Get(QueryTuple),
+    /// Like `Read`, but parks until a matching tuple is written instead of answering `None`.
+    ReadWait(QueryTuple),
+    /// Like `Get`, but parks until a matching tuple is written instead of answering `None`.
+    GetWait(QueryTuple),
+    ReadAll(QueryTuple, Option<usize>),
+    GetAll(QueryTuple, Option<usize>),
+    Count(QueryTuple),
+    /// Takes one tuple matching the query and writes the tuple in its place.
+    Replace(QueryTuple, Tuple),
 }
@@ -7,5 +7,9 @@ pub(crate) enum CommandResult {
     Write,
     Read(Option<Tuple>),
     Get(Option<Tuple>),
+    ReadAll(Vec<Tuple>),
+    GetAll(Vec<Tuple>),
+    Count(usize),
+    Replace(Option<Tuple>),
     Error(Error),
 }
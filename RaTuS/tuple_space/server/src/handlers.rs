@@ -1,26 +1,78 @@
+use std::collections::VecDeque;
 use std::convert::Infallible;
+use std::time::Duration;
 use tokio::sync::oneshot;
 use warp::http::StatusCode;
 
 use crate::command::Command;
 use crate::command_result::CommandResult;
-use crate::types::{CommandReceive, CommandSend};
+use crate::limit::LimitQuery;
+use crate::ttl::TtlQuery;
+use crate::types::{CommandReceive, CommandSend, TupleStore};
+use crate::wait::WaitQuery;
 
 use ts_core::{
     query_tuple::QueryTuple,
     store::Store,
     tuple::Tuple,
-    vec_store::VecStore,
-    mutex_store::MutexStore,
 };
 
 use system::Logger;
 
+/// How often expired tuples are dropped from the store.
+const SWEEP_INTERVAL: Duration = Duration::from_millis(100);
+
+/// A long-poll `read`/`get` parked until a matching tuple is written.
+struct Waiter {
+    query_tuple: QueryTuple,
+    take: bool,
+    response: oneshot::Sender<CommandResult>,
+}
+
+/// Parks `waiter` until a matching tuple is written. Waiters whose caller has gone are dropped
+/// first, so they do not pile up while nothing is written.
+fn park(waiters: &mut VecDeque<Waiter>, waiter: Waiter) {
+    waiters.retain(|waiter| !waiter.response.is_closed());
+    waiters.push_back(waiter);
+}
+
+/// Offers a freshly written tuple to the parked waiters, oldest first.
+///
+/// Every matching reader gets a copy, until the first matching taker consumes the tuple.
+/// Returns the tuple back if no taker claimed it, so it can be written to the store.
+fn offer_to_waiters(waiters: &mut VecDeque<Waiter>, tuple: Tuple) -> Option<Tuple> {
+    waiters.retain(|waiter| !waiter.response.is_closed());
+
+    let mut tuple = tuple;
+    let mut index = 0;
+    while index < waiters.len() {
+        if waiters[index].query_tuple != tuple {
+            index += 1;
+            continue;
+        }
+        let waiter = waiters.remove(index).expect("waiter index in bounds");
+        if !waiter.take {
+            let _ = waiter.response.send(CommandResult::Read(Some(tuple.clone())));
+            continue;
+        }
+        // A taker that gave up between the `is_closed` check and now hands the tuple back.
+        match waiter.response.send(CommandResult::Get(Some(tuple))) {
+            Ok(()) => return None,
+            Err(CommandResult::Get(Some(returned))) => tuple = returned,
+            Err(_) => unreachable!("Get result is returned unchanged"),
+        }
+    }
+    Some(tuple)
+}
+
 pub(crate) fn spawn_tuple_space_handler(
     mut command_rx: CommandReceive,
 ) -> tokio::task::JoinHandle<()> {
     tokio::spawn(async move {
-        let mut mutex_store = MutexStore::<VecStore>::default();
+        let mut mutex_store = TupleStore::default();
+        // Exits on its own once the store is dropped with this task.
+        mutex_store.spawn_sweeper(SWEEP_INTERVAL);
+        let mut waiters = VecDeque::<Waiter>::new();
 
         while let Some((command, response)) = command_rx.recv().await {
             Logger::info(&format!("Command {:?} received", command), true);
@@ -29,9 +81,15 @@ pub(crate) fn spawn_tuple_space_handler(
                     Ok(size) => CommandResult::Size(size),
                     Err(error) => CommandResult::Error(error.into()),
                 },
-                Command::Write(tuple) => match mutex_store.write(&tuple) {
-                    Ok(()) => CommandResult::Write,
-                    Err(error) => CommandResult::Error(error.into()),
+                Command::Write(tuple, ttl) => match offer_to_waiters(&mut waiters, tuple) {
+                    Some(tuple) => match match ttl {
+                        Some(ttl) => mutex_store.write_with_ttl(&tuple, ttl),
+                        None => mutex_store.write(&tuple),
+                    } {
+                        Ok(()) => CommandResult::Write,
+                        Err(error) => CommandResult::Error(error.into()),
+                    },
+                    None => CommandResult::Write,
                 },
                 Command::Read(query_tuple) => match mutex_store.read(&query_tuple) {
                     Ok(tuple_option) => CommandResult::Read(tuple_option),
@@ -41,6 +99,54 @@ pub(crate) fn spawn_tuple_space_handler(
                     Ok(tuple_option) => CommandResult::Get(tuple_option),
                     Err(error) => CommandResult::Error(error.into()),
                 },
+                Command::ReadWait(query_tuple) => match mutex_store.read(&query_tuple) {
+                    Ok(Some(tuple)) => CommandResult::Read(Some(tuple)),
+                    Ok(None) => {
+                        Logger::info(&format!("Parking read {:?}", query_tuple), true);
+                        park(&mut waiters, Waiter { query_tuple, take: false, response });
+                        continue;
+                    }
+                    Err(error) => CommandResult::Error(error.into()),
+                },
+                Command::GetWait(query_tuple) => match mutex_store.get(&query_tuple) {
+                    Ok(Some(tuple)) => CommandResult::Get(Some(tuple)),
+                    Ok(None) => {
+                        Logger::info(&format!("Parking get {:?}", query_tuple), true);
+                        park(&mut waiters, Waiter { query_tuple, take: true, response });
+                        continue;
+                    }
+                    Err(error) => CommandResult::Error(error.into()),
+                },
+                Command::ReadAll(query_tuple, limit) => {
+                    match mutex_store.read_all(&query_tuple, limit) {
+                        Ok(tuples) => CommandResult::ReadAll(tuples),
+                        Err(error) => CommandResult::Error(error.into()),
+                    }
+                }
+                Command::GetAll(query_tuple, limit) => {
+                    match mutex_store.get_all(&query_tuple, limit) {
+                        Ok(tuples) => CommandResult::GetAll(tuples),
+                        Err(error) => CommandResult::Error(error.into()),
+                    }
+                }
+                Command::Count(query_tuple) => match mutex_store.count(&query_tuple) {
+                    Ok(count) => CommandResult::Count(count),
+                    Err(error) => CommandResult::Error(error.into()),
+                },
+                // A parked taker may claim the replacement, so the write half goes through the
+                // waiters like any other write. Commands are handled one at a time, so no other
+                // command runs between the take and the write.
+                Command::Replace(query_tuple, tuple) => match mutex_store.get(&query_tuple) {
+                    Ok(Some(replaced)) => match offer_to_waiters(&mut waiters, tuple) {
+                        Some(tuple) => match mutex_store.write(&tuple) {
+                            Ok(()) => CommandResult::Replace(Some(replaced)),
+                            Err(error) => CommandResult::Error(error.into()),
+                        },
+                        None => CommandResult::Replace(Some(replaced)),
+                    },
+                    Ok(None) => CommandResult::Replace(None),
+                    Err(error) => CommandResult::Error(error.into()),
+                },
             };
             Logger::info(&format!("CommandResult {:?}", command_result), true);
             match response.send(command_result) {
@@ -51,6 +157,28 @@ pub(crate) fn spawn_tuple_space_handler(
     })
 }
 
+/// Waits for the result of a command, answering `timed_out` once `wait` elapses if one is given.
+///
+/// On timeout the channel is closed before giving up, so a tuple handed over at the last moment
+/// is still received here rather than silently dropped.
+async fn receive_result(
+    mut response_rx: oneshot::Receiver<CommandResult>,
+    wait: Option<Duration>,
+    timed_out: CommandResult,
+) -> std::result::Result<CommandResult, oneshot::error::RecvError> {
+    let wait = match wait {
+        Some(wait) => wait,
+        None => return response_rx.await,
+    };
+    match tokio::time::timeout(wait, &mut response_rx).await {
+        Ok(result) => result,
+        Err(_elapsed) => {
+            response_rx.close();
+            Ok(response_rx.try_recv().unwrap_or(timed_out))
+        }
+    }
+}
+
 pub(crate) async fn size(
     command_tx: CommandSend,
 ) -> std::result::Result<Box<dyn warp::Reply>, Infallible> {
@@ -82,12 +210,20 @@ pub(crate) async fn size(
 }
 
 pub(crate) async fn write(
+    ttl_query: TtlQuery,
     tuple: Tuple,
     command_tx: CommandSend,
 ) -> std::result::Result<impl warp::Reply, Infallible> {
     Logger::info(&format!("Write {:?}", tuple), true);
+    let ttl = match ttl_query.duration() {
+        Ok(ttl) => ttl,
+        Err(error) => {
+            Logger::error(&error, true);
+            return Ok(StatusCode::BAD_REQUEST);
+        }
+    };
     let (response_tx, response_rx) = oneshot::channel();
-    match command_tx.send((Command::Write(tuple), response_tx)).await {
+    match command_tx.send((Command::Write(tuple, ttl), response_tx)).await {
         Ok(_) => (),
         Err(error) => {
             Logger::error(&format!("Tuple Space error {:?}", error), true);
@@ -111,21 +247,30 @@ pub(crate) async fn write(
 }
 
 pub(crate) async fn read(
+    wait_query: WaitQuery,
     query_tuple: QueryTuple,
     command_tx: CommandSend,
 ) -> std::result::Result<Box<dyn warp::Reply>, Infallible> {
+    let wait = match wait_query.duration() {
+        Ok(wait) => wait,
+        Err(error) => {
+            Logger::error(&error, true);
+            return Ok(Box::new(StatusCode::BAD_REQUEST));
+        }
+    };
+    let command = match wait {
+        Some(_) => Command::ReadWait(query_tuple),
+        None => Command::Read(query_tuple),
+    };
     let (response_tx, response_rx) = oneshot::channel();
-    match command_tx
-        .send((Command::Read(query_tuple), response_tx))
-        .await
-    {
+    match command_tx.send((command, response_tx)).await {
         Ok(_) => (),
         Err(error) => {
             Logger::error(&format!("Tuple Space error {:?}", error), true);
             return Ok(Box::new(StatusCode::INTERNAL_SERVER_ERROR));
         }
     }
-    match response_rx.await {
+    match receive_result(response_rx, wait, CommandResult::Read(None)).await {
         Ok(CommandResult::Read(Some(tuple))) => {
             Logger::info(&format!("Tuple found {:?}", tuple), true);
             Ok(Box::new(warp::reply::json(&tuple)))
@@ -146,21 +291,30 @@ pub(crate) async fn read(
 }
 
 pub(crate) async fn get(
+    wait_query: WaitQuery,
     query_tuple: QueryTuple,
     command_tx: CommandSend,
 ) -> std::result::Result<Box<dyn warp::Reply>, Infallible> {
+    let wait = match wait_query.duration() {
+        Ok(wait) => wait,
+        Err(error) => {
+            Logger::error(&error, true);
+            return Ok(Box::new(StatusCode::BAD_REQUEST));
+        }
+    };
+    let command = match wait {
+        Some(_) => Command::GetWait(query_tuple),
+        None => Command::Get(query_tuple),
+    };
     let (response_tx, response_rx) = oneshot::channel();
-    match command_tx
-        .send((Command::Get(query_tuple), response_tx))
-        .await
-    {
+    match command_tx.send((command, response_tx)).await {
         Ok(_) => (),
         Err(error) => {
             Logger::error(&format!("Tuple Space error {:?}", error), true);
             return Ok(Box::new(StatusCode::INTERNAL_SERVER_ERROR));
         }
     }
-    match response_rx.await {
+    match receive_result(response_rx, wait, CommandResult::Get(None)).await {
         Ok(CommandResult::Get(Some(tuple))) => {
             Logger::info(&format!("Tuple found {:?}", tuple), true);
             Ok(Box::new(warp::reply::json(&tuple)))
@@ -179,3 +333,134 @@ pub(crate) async fn get(
         }
     }
 }
+
+pub(crate) async fn read_all(
+    limit_query: LimitQuery,
+    query_tuple: QueryTuple,
+    command_tx: CommandSend,
+) -> std::result::Result<Box<dyn warp::Reply>, Infallible> {
+    let (response_tx, response_rx) = oneshot::channel();
+    match command_tx
+        .send((Command::ReadAll(query_tuple, limit_query.limit), response_tx))
+        .await
+    {
+        Ok(_) => (),
+        Err(error) => {
+            Logger::error(&format!("Tuple Space error {:?}", error), true);
+            return Ok(Box::new(StatusCode::INTERNAL_SERVER_ERROR));
+        }
+    }
+    match response_rx.await {
+        Ok(CommandResult::ReadAll(tuples)) => {
+            Logger::info(&format!("{} tuples found", tuples.len()), true);
+            Ok(Box::new(warp::reply::json(&tuples)))
+        }
+        Err(error) => {
+            Logger::error(&format!("Error: {:?}", error), true);
+            Ok(Box::new(StatusCode::INTERNAL_SERVER_ERROR))
+        }
+        unexpected => {
+            Logger::error(&format!("Unexpected response: {:?}", unexpected), true);
+            Ok(Box::new(StatusCode::INTERNAL_SERVER_ERROR))
+        }
+    }
+}
+
+pub(crate) async fn get_all(
+    limit_query: LimitQuery,
+    query_tuple: QueryTuple,
+    command_tx: CommandSend,
+) -> std::result::Result<Box<dyn warp::Reply>, Infallible> {
+    let (response_tx, response_rx) = oneshot::channel();
+    match command_tx
+        .send((Command::GetAll(query_tuple, limit_query.limit), response_tx))
+        .await
+    {
+        Ok(_) => (),
+        Err(error) => {
+            Logger::error(&format!("Tuple Space error {:?}", error), true);
+            return Ok(Box::new(StatusCode::INTERNAL_SERVER_ERROR));
+        }
+    }
+    match response_rx.await {
+        Ok(CommandResult::GetAll(tuples)) => {
+            Logger::info(&format!("{} tuples taken", tuples.len()), true);
+            Ok(Box::new(warp::reply::json(&tuples)))
+        }
+        Err(error) => {
+            Logger::error(&format!("Error: {:?}", error), true);
+            Ok(Box::new(StatusCode::INTERNAL_SERVER_ERROR))
+        }
+        unexpected => {
+            Logger::error(&format!("Unexpected response: {:?}", unexpected), true);
+            Ok(Box::new(StatusCode::INTERNAL_SERVER_ERROR))
+        }
+    }
+}
+
+pub(crate) async fn count(
+    query_tuple: QueryTuple,
+    command_tx: CommandSend,
+) -> std::result::Result<Box<dyn warp::Reply>, Infallible> {
+    let (response_tx, response_rx) = oneshot::channel();
+    match command_tx
+        .send((Command::Count(query_tuple), response_tx))
+        .await
+    {
+        Ok(_) => (),
+        Err(error) => {
+            Logger::error(&format!("Tuple Space error {:?}", error), true);
+            return Ok(Box::new(StatusCode::INTERNAL_SERVER_ERROR));
+        }
+    }
+    match response_rx.await {
+        Ok(CommandResult::Count(count)) => {
+            Logger::info(&format!("Count: {}", count), true);
+            Ok(Box::new(warp::reply::json(&count)))
+        }
+        Err(error) => {
+            Logger::error(&format!("Error: {:?}", error), true);
+            Ok(Box::new(StatusCode::INTERNAL_SERVER_ERROR))
+        }
+        unexpected => {
+            Logger::error(&format!("Unexpected response: {:?}", unexpected), true);
+            Ok(Box::new(StatusCode::INTERNAL_SERVER_ERROR))
+        }
+    }
+}
+
+pub(crate) async fn replace(
+    (query_tuple, tuple): (QueryTuple, Tuple),
+    command_tx: CommandSend,
+) -> std::result::Result<Box<dyn warp::Reply>, Infallible> {
+    Logger::info(&format!("Replace {:?} with {:?}", query_tuple, tuple), true);
+    let (response_tx, response_rx) = oneshot::channel();
+    match command_tx
+        .send((Command::Replace(query_tuple, tuple), response_tx))
+        .await
+    {
+        Ok(_) => (),
+        Err(error) => {
+            Logger::error(&format!("Tuple Space error {:?}", error), true);
+            return Ok(Box::new(StatusCode::INTERNAL_SERVER_ERROR));
+        }
+    }
+    match response_rx.await {
+        Ok(CommandResult::Replace(Some(replaced))) => {
+            Logger::info(&format!("Tuple replaced {:?}", replaced), true);
+            Ok(Box::new(warp::reply::json(&replaced)))
+        }
+        Ok(CommandResult::Replace(None)) => {
+            Logger::info("Tuple not found", true);
+            Ok(Box::new(StatusCode::NOT_FOUND))
+        }
+        Err(error) => {
+            Logger::error(&format!("Error: {:?}", error), true);
+            Ok(Box::new(StatusCode::INTERNAL_SERVER_ERROR))
+        }
+        unexpected => {
+            Logger::error(&format!("Unexpected response: {:?}", unexpected), true);
+            Ok(Box::new(StatusCode::INTERNAL_SERVER_ERROR))
+        }
+    }
+}
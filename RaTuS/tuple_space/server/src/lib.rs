@@ -3,8 +3,11 @@ mod command_result;
 mod config;
 mod error;
 mod handlers;
+mod limit;
 mod routes;
+mod ttl;
 mod types;
+mod wait;
 
 pub use crate::config::Config;
 use crate::types::CommandPayload;
@@ -0,0 +1,7 @@
+use serde::Deserialize;
+
+/// Query string accepted by the bulk endpoints, e.g. `/get-all?limit=10`.
+#[derive(Deserialize, Debug, Default)]
+pub(crate) struct LimitQuery {
+    pub(crate) limit: Option<usize>,
+}
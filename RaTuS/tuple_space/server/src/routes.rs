@@ -1,5 +1,8 @@
 use crate::handlers;
+use crate::limit::LimitQuery;
+use crate::ttl::TtlQuery;
 use crate::types::CommandSend;
+use crate::wait::WaitQuery;
 use std::convert::Infallible;
 use warp::Filter;
 
@@ -7,6 +10,10 @@ const SIZE_PATH: &str = "size";
 const WRITE_PATH: &str = "write";
 const READ_PATH: &str = "read";
 const GET_PATH: &str = "get";
+const READ_ALL_PATH: &str = "read-all";
+const GET_ALL_PATH: &str = "get-all";
+const COUNT_PATH: &str = "count";
+const REPLACE_PATH: &str = "replace";
 
 fn with_command_tx(
     command_tx: CommandSend,
@@ -28,6 +35,7 @@ fn write(
 ) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
     warp::path(WRITE_PATH)
         .and(warp::post())
+        .and(warp::query::<TtlQuery>())
         .and(warp::body::json())
         .and(with_command_tx(command_tx))
         .and_then(handlers::write)
@@ -38,6 +46,7 @@ fn read(
 ) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
     warp::path(READ_PATH)
         .and(warp::post())
+        .and(warp::query::<WaitQuery>())
         .and(warp::body::json())
         .and(with_command_tx(command_tx))
         .and_then(handlers::read)
@@ -48,16 +57,64 @@ fn get(
 ) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
     warp::path(GET_PATH)
         .and(warp::post())
+        .and(warp::query::<WaitQuery>())
         .and(warp::body::json())
         .and(with_command_tx(command_tx))
         .and_then(handlers::get)
 }
 
+fn read_all(
+    command_tx: CommandSend,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path(READ_ALL_PATH)
+        .and(warp::post())
+        .and(warp::query::<LimitQuery>())
+        .and(warp::body::json())
+        .and(with_command_tx(command_tx))
+        .and_then(handlers::read_all)
+}
+
+fn get_all(
+    command_tx: CommandSend,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path(GET_ALL_PATH)
+        .and(warp::post())
+        .and(warp::query::<LimitQuery>())
+        .and(warp::body::json())
+        .and(with_command_tx(command_tx))
+        .and_then(handlers::get_all)
+}
+
+fn count(
+    command_tx: CommandSend,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path(COUNT_PATH)
+        .and(warp::post())
+        .and(warp::body::json())
+        .and(with_command_tx(command_tx))
+        .and_then(handlers::count)
+}
+
+/// Body is a `[query_tuple, tuple]` pair.
+fn replace(
+    command_tx: CommandSend,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path(REPLACE_PATH)
+        .and(warp::post())
+        .and(warp::body::json())
+        .and(with_command_tx(command_tx))
+        .and_then(handlers::replace)
+}
+
 pub(crate) fn tuple_routes(
     command_tx: CommandSend,
 ) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
     size(command_tx.clone())
         .or(write(command_tx.clone()))
         .or(read(command_tx.clone()))
-        .or(get(command_tx))
+        .or(get(command_tx.clone()))
+        .or(read_all(command_tx.clone()))
+        .or(get_all(command_tx.clone()))
+        .or(count(command_tx.clone()))
+        .or(replace(command_tx))
 }
@@ -0,0 +1,16 @@
+use serde::Deserialize;
+use std::time::Duration;
+
+use crate::wait::parse_duration;
+
+/// Query string accepted by `/write` to let the tuple expire, e.g. `/write?ttl=30s`.
+#[derive(Deserialize, Debug, Default)]
+pub(crate) struct TtlQuery {
+    ttl: Option<String>,
+}
+
+impl TtlQuery {
+    pub(crate) fn duration(&self) -> std::result::Result<Option<Duration>, String> {
+        self.ttl.as_deref().map(parse_duration).transpose()
+    }
+}
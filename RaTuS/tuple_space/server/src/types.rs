@@ -2,7 +2,12 @@ use crate::command::Command;
 use crate::command_result::CommandResult;
 use tokio::sync::mpsc;
 use tokio::sync::oneshot;
+use ts_core::indexed_store::IndexedStore;
+use ts_core::mutex_store::MutexStore;
 
 pub(crate) type CommandPayload = (Command, oneshot::Sender<CommandResult>);
 pub(crate) type CommandSend = mpsc::Sender<CommandPayload>;
 pub(crate) type CommandReceive = mpsc::Receiver<CommandPayload>;
+
+/// The store served by the tuple space handler, e.g. `MutexStore<VecStore>` for a plain linear scan.
+pub(crate) type TupleStore = MutexStore<IndexedStore>;
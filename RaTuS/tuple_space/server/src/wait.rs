@@ -0,0 +1,34 @@
+use serde::Deserialize;
+use std::time::Duration;
+
+/// Query string accepted by the long-poll variants of `/read` and `/get`, e.g. `/get?wait=30s`.
+///
+/// The wait accepts `ms`, `s` and `m` suffixes; a bare number is read as seconds.
+#[derive(Deserialize, Debug, Default)]
+pub(crate) struct WaitQuery {
+    wait: Option<String>,
+}
+
+impl WaitQuery {
+    pub(crate) fn duration(&self) -> std::result::Result<Option<Duration>, String> {
+        self.wait.as_deref().map(parse_duration).transpose()
+    }
+}
+
+/// Parses a duration such as `500ms`, `30s` or `2m`; a bare number is read as seconds.
+pub(crate) fn parse_duration(wait: &str) -> std::result::Result<Duration, String> {
+    let wait = wait.trim();
+    let (value, unit_millis) = if let Some(value) = wait.strip_suffix("ms") {
+        (value, 1)
+    } else if let Some(value) = wait.strip_suffix('s') {
+        (value, 1_000)
+    } else if let Some(value) = wait.strip_suffix('m') {
+        (value, 60_000)
+    } else {
+        (wait, 1_000)
+    };
+    match value.trim().parse::<u64>() {
+        Ok(value) => Ok(Duration::from_millis(value.saturating_mul(unit_millis))),
+        Err(_) => Err(format!("Invalid wait duration {:?}", wait)),
+    }
+}